@@ -0,0 +1,125 @@
+//! Display backlights, from /sys/class/backlight, for `psybeam.backlight`.
+//!
+//! Writing to sysfs usually needs a udev rule granting access to the brightness file,
+//! so when that fails, brightness is set through logind instead,
+//! which allows the user of the active session to change it.
+//! That's done by running busctl, which comes with systemd.
+
+use crate::{command, sys};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Duration;
+use std::{fs, io};
+
+#[derive(Debug)]
+pub struct Backlight {
+    pub name: Rc<str>,
+    /// "raw", "platform", or "firmware".
+    pub kind: Rc<str>,
+    pub brightness: i64,
+    pub max_brightness: i64,
+}
+
+impl Backlight {
+    /// Percent, from 0 to 100.
+    pub fn percent(&self) -> i64 {
+        if self.max_brightness > 0 {
+            self.brightness * 100 / self.max_brightness
+        } else {
+            0
+        }
+    }
+}
+
+fn path(name: &str) -> io::Result<PathBuf> {
+    let path = sys::sysfs(Path::new("class/backlight").join(name));
+    if path.is_dir() {
+        Ok(path)
+    } else {
+        Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no backlight named {name}"),
+        ))
+    }
+}
+
+fn read(name: &str, path: &Path) -> io::Result<Backlight> {
+    let property = |property: &str| -> io::Result<String> {
+        Ok(fs::read_to_string(path.join(property))?.trim().to_owned())
+    };
+    let number = |file: &str| -> io::Result<i64> {
+        property(file)?.parse().map_err(|_| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("malformed {file} for backlight {name}"),
+            )
+        })
+    };
+    Ok(Backlight {
+        name: name.into(),
+        kind: property("type").map_or_else(|_| "unknown".into(), |kind| kind.into()),
+        // The brightness the hardware reports, which can lag behind the requested one.
+        brightness: number("actual_brightness").or_else(|_| number("brightness"))?,
+        max_brightness: number("max_brightness")?,
+    })
+}
+
+/// Every backlight, sorted by name.
+pub fn backlights() -> io::Result<Vec<Backlight>> {
+    let mut names = fs::read_dir(sys::sysfs("class/backlight"))?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .collect::<Vec<_>>();
+    names.sort();
+    names.iter().map(|name| read(name, &path(name)?)).collect()
+}
+
+/// A single backlight by name, such as "intel_backlight".
+pub fn backlight(name: &str) -> io::Result<Backlight> {
+    read(name, &path(name)?)
+}
+
+/// Sets the brightness of a backlight, clamped to its range.
+pub fn set(name: &str, brightness: i64) -> io::Result<()> {
+    let path = path(name)?;
+    let brightness = brightness.clamp(0, read(name, &path)?.max_brightness);
+    match fs::write(path.join("brightness"), brightness.to_string()) {
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => set_with_logind(name, brightness)
+            .map_err(|logind| {
+                io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    format!(
+                        "failed to set brightness of {name}: {e}, and through logind: {logind}"
+                    ),
+                )
+            }),
+        result => result,
+    }
+}
+
+/// Calls `SetBrightness` on the caller's logind session.
+///
+/// busctl runs in the background, so only failing to start it is returned;
+/// any later failure is logged instead.
+fn set_with_logind(name: &str, brightness: i64) -> io::Result<()> {
+    command::detach(command::Spec {
+        argv: [
+            "busctl",
+            "call",
+            "org.freedesktop.login1",
+            "/org/freedesktop/login1/session/auto",
+            "org.freedesktop.login1.Session",
+            "SetBrightness",
+            "ssu",
+            "backlight",
+            name,
+            &brightness.to_string(),
+        ]
+        .map(Rc::from)
+        .into(),
+        stdin: None,
+        env: [].into(),
+        clear_env: false,
+        cwd: None,
+        timeout: Duration::from_secs(5),
+    })
+}
@@ -1,4 +1,7 @@
-use crate::{UserWidget, Widget, WidgetRefreshRate};
+use crate::{
+    UserWidget, Widget, WidgetRefreshRate, WidgetState, backlight, color, command, filesystem,
+    format, host, json, net, pattern, power, render, sys,
+};
 use espy::extern_impl;
 use std::{rc::Rc, time::Duration};
 
@@ -32,6 +35,269 @@ impl espy::ExternOwned for Label {
     }
 }
 
+#[derive(Debug)]
+pub enum ImageSource {
+    Path(Rc<str>),
+    /// An icon name, resolved through the configured icon theme.
+    Icon(Rc<str>),
+}
+
+#[derive(Debug)]
+pub struct Image {
+    pub source: ImageSource,
+    /// Images are scaled to fit within a square of this many pixels.
+    pub size: u32,
+
+    pub x: i32,
+    /// Vertically centered within the widget if not specified.
+    pub y: Option<i32>,
+}
+
+impl espy::ExternOwned for Image {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum PathSegment {
+    MoveTo {
+        x: i32,
+        y: i32,
+    },
+    LineTo {
+        x: i32,
+        y: i32,
+    },
+    /// Angles are in degrees, measured clockwise from the positive x axis.
+    Arc {
+        x: i32,
+        y: i32,
+        radius: u32,
+        start: i32,
+        end: i32,
+    },
+    Close,
+}
+
+impl espy::ExternOwned for PathSegment {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug)]
+pub enum PathPaint {
+    Stroke { width: u32 },
+    Fill,
+}
+
+#[derive(Debug)]
+pub struct Path {
+    pub segments: Box<[PathSegment]>,
+    pub paint: PathPaint,
+    /// Red, green, blue, and alpha.
+    pub color: [u8; 4],
+}
+
+impl espy::ExternOwned for Path {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug)]
+pub struct History {
+    /// Oldest first.
+    pub samples: Box<[i64]>,
+    pub capacity: usize,
+}
+
+impl espy::ExternOwned for History {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug)]
+pub enum GraphStyle {
+    Line,
+    Area,
+    Bar,
+}
+
+#[derive(Debug)]
+pub struct Graph {
+    pub samples: Box<[i64]>,
+    /// Graphs are scaled so that a full history spans the widget.
+    pub capacity: usize,
+    pub style: GraphStyle,
+    pub min: i64,
+    /// Scaled to the largest sample if not specified.
+    pub max: Option<i64>,
+    pub color: [u8; 4],
+    pub background: Option<[u8; 4]>,
+}
+
+impl espy::ExternOwned for Graph {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+#[derive(Debug)]
+pub enum BarDirection {
+    /// Fills from left to right.
+    Horizontal,
+    /// Fills from bottom to top.
+    Vertical,
+}
+
+#[derive(Debug)]
+pub struct Bar {
+    pub value: i64,
+    pub min: i64,
+    pub max: i64,
+    pub direction: BarDirection,
+    pub color: [u8; 4],
+    pub background: Option<[u8; 4]>,
+    /// Thresholds paired with the color to use once the value reaches them,
+    /// sorted in ascending order.
+    pub stops: Box<[(i64, [u8; 4])]>,
+
+    pub x: i32,
+    pub y: i32,
+    /// Fills the rest of the widget if not specified.
+    pub width: Option<u32>,
+    /// Fills the rest of the widget if not specified.
+    pub height: Option<u32>,
+}
+
+impl Bar {
+    /// The fill color after applying any stops the value has reached.
+    pub fn fill(&self) -> [u8; 4] {
+        self.stops
+            .iter()
+            .rev()
+            .find(|(threshold, _)| self.value >= *threshold)
+            .map_or(self.color, |(_, color)| *color)
+    }
+}
+
+impl espy::ExternOwned for Bar {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
+/// A point in time, along with the time zone it's displayed in.
+#[derive(Debug)]
+pub struct Time(pub jiff::Zoned);
+
+impl espy::ExternOwned for Time {
+    fn index<'host>(
+        self: Rc<Self>,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::Owned(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{self:?}")
+    }
+}
+
 impl espy::Extern for Widget {
     fn index<'host>(
         &'host self,
@@ -76,6 +342,30 @@ impl espy::ExternOwned for Widget {
     }
 }
 
+impl espy::Extern for WidgetRefreshRate {
+    fn index<'host>(
+        &'host self,
+        index: espy::Value<'host>,
+    ) -> Result<espy::Value<'host>, espy::Error<'host>> {
+        Err(espy::Error::IndexNotFound {
+            index,
+            container: espy::Value::borrow(self),
+        })
+    }
+
+    fn any(&self) -> Option<&dyn std::any::Any> {
+        Some(self)
+    }
+
+    fn as_static(&self) -> Option<espy::Value<'static>> {
+        Some(espy::Value::owned(Rc::new(self.clone())))
+    }
+
+    fn debug(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        std::write!(f, "{self:?}")
+    }
+}
+
 impl espy::ExternOwned for WidgetRefreshRate {
     fn index<'host>(
         self: Rc<Self>,
@@ -99,41 +389,147 @@ impl espy::ExternOwned for WidgetRefreshRate {
 extern_impl! {
     #[espy(debug = "psybeam module")]
     pub struct Lib {
+        backlight: espy::Value::borrow(&BacklightLib),
         color: espy::Value::borrow(&ColorLib),
+        bar: espy::Function::borrow(&BarFn),
         command: espy::Function::borrow(&CommandFn),
+        env: espy::Value::borrow(&EnvLib),
+        format: espy::Value::borrow(&FormatLib),
+        fs: espy::Value::borrow(&FsLib),
+        graph: espy::Function::borrow(&GraphFn),
+        history: espy::Value::borrow(&HistoryLib),
+        icon: espy::Function::borrow(&IconFn),
+        image: espy::Function::borrow(&ImageFn),
+        json: espy::Value::borrow(&JsonLib),
+        net: espy::Value::borrow(&NetLib),
         read_to_string: espy::Function::borrow(&ReadToStringFn),
+        regex: espy::Value::borrow(&RegexLib),
         label_color: espy::Function::borrow(&LabelColorFn),
+        path: espy::Value::borrow(&PathLib),
+        power: espy::Value::borrow(&PowerLib),
+        stream: espy::Value::borrow(&StreamLib),
+        sys: espy::Value::borrow(&SysLib),
+        time: espy::Value::borrow(&TimeLib),
         widget: espy::Value::borrow(&WidgetLib),
     }
 }
 
+/// Reads a program and its arguments, given either as a tuple of strings or a single program name.
+fn argv<'host>(value: espy::Value<'host>) -> Result<Box<[Rc<str>]>, espy::Error<'host>> {
+    let argv = match value {
+        espy::Value::Tuple(args) => args
+            .values()
+            .map(|argument| argument.clone().into_str())
+            .collect::<Result<Box<[_]>, _>>()?,
+        program => [program.into_str()?].into(),
+    };
+    if argv.is_empty() {
+        return Err(espy::Error::Other("expected a program to run".into()));
+    }
+    Ok(argv)
+}
+
+/// How long commands may run if no `timeout` is given.
+const DEFAULT_COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
 extern_impl! {
     #[espy(debug = "psybeam.command function")]
     fn CommandFn<'host>(&self, argument) {
-        let mut command = std::process::Command::new(&*argument.get(0)?.into_str()?);
-        for argument in argument.into_tuple()?.values().skip(1) {
-            command.arg(&*argument.clone().into_str()?);
-        }
-        // TODO: espystandard or espy should provide a result type.
-        let (status, stdout, stderr) = command.output().map_or_else(
-            |e| (255, Rc::from(""), Rc::from(e.to_string().as_str())),
-            |output| {
-                (
-                    output.status.code().unwrap_or(255),
-                    String::from_utf8_lossy(&output.stdout).into(),
-                    String::from_utf8_lossy(&output.stderr).into(),
-                )
+        // Either a tuple of arguments,
+        // or named `args` alongside options such as `timeout` (in milliseconds).
+        let (args, options) = match &argument {
+            espy::Value::Tuple(tuple) if let Some(args) = tuple.find_value("args") => {
+                (args.clone(), Some(tuple.clone()))
+            }
+            _ => (argument, None),
+        };
+        let option = |name| {
+            options
+                .as_ref()
+                .and_then(|options| options.find_value(name))
+                .cloned()
+        };
+        let spec = command::Spec {
+            argv: argv(args)?,
+            stdin: option("stdin").map(|stdin| stdin.into_str()).transpose()?,
+            // A tuple of (name, value) pairs.
+            env: match option("env") {
+                Some(env) => env
+                    .into_tuple()?
+                    .values()
+                    .map(|pair| Ok((pair.get(0)?.into_str()?, pair.get(1)?.into_str()?)))
+                    .collect::<Result<_, espy::Error>>()?,
+                None => [].into(),
             },
-        );
-        Ok(espy::Value::Tuple(
-            [
-                (Rc::from("status"), espy::Value::I64(status as i64)),
-                (Rc::from("stdout"), espy::Value::String(stdout)),
-                (Rc::from("stderr"), espy::Value::String(stderr)),
+            clear_env: option("clear_env")
+                .map(|clear_env| clear_env.into_bool())
+                .transpose()?
+                .unwrap_or(false),
+            cwd: option("cwd").map(|cwd| cwd.into_str()).transpose()?,
+            timeout: option("timeout")
+                .map(|timeout| timeout.into_i64())
+                .transpose()?
+                .map(|timeout| timeout.try_into().map(Duration::from_millis))
+                .transpose()?
+                .unwrap_or(DEFAULT_COMMAND_TIMEOUT),
+        };
+        let (output, running) = command::run(spec);
+        let running = (Rc::from("running"), espy::Value::from(running));
+        // TODO: espystandard or espy should provide a result type.
+        // Until then, `result` names the variant and `ok` is true for a successful exit.
+        let Some(output) = output else {
+            // The first run hasn't finished yet.
+            return Ok(espy::Value::Tuple(
+                [
+                    (Rc::from("result"), espy::Value::String("pending".into())),
+                    (Rc::from("ok"), espy::Value::from(false)),
+                    running,
+                    (Rc::from("stdout"), espy::Value::String("".into())),
+                    (Rc::from("stderr"), espy::Value::String("".into())),
+                ]
+                // TODO: espy doesn't rexport Tuple
+                .into(),
+            ));
+        };
+        let stdout = (Rc::from("stdout"), espy::Value::String(output.stdout));
+        let stderr = (Rc::from("stderr"), espy::Value::String(output.stderr));
+        Ok(espy::Value::Tuple(match output.status {
+            command::Status::Exited(status) => [
+                (Rc::from("result"), espy::Value::String("exited".into())),
+                (Rc::from("ok"), espy::Value::from(status == 0)),
+                (Rc::from("status"), espy::Value::I64(status.into())),
+                running,
+                stdout,
+                stderr,
             ]
-            // TODO: espy doesn't rexport Tuple
             .into(),
-        ))
+            command::Status::Signaled(signal) => [
+                (Rc::from("result"), espy::Value::String("signaled".into())),
+                (Rc::from("ok"), espy::Value::from(false)),
+                (Rc::from("signal"), espy::Value::I64(signal.into())),
+                running,
+                stdout,
+                stderr,
+            ]
+            .into(),
+            command::Status::TimedOut => [
+                (Rc::from("result"), espy::Value::String("timed_out".into())),
+                (Rc::from("ok"), espy::Value::from(false)),
+                running,
+                stdout,
+                stderr,
+            ]
+            .into(),
+            command::Status::Failed(error) => [
+                (Rc::from("result"), espy::Value::String("failed".into())),
+                (Rc::from("ok"), espy::Value::from(false)),
+                (Rc::from("error"), espy::Value::String(error)),
+                running,
+                stdout,
+                stderr,
+            ]
+            .into(),
+        }))
     }
 }
 
@@ -146,97 +542,623 @@ extern_impl! {
     }
 }
 
+/// Unpacks a color produced by `psybeam.color` into red, green, blue, and alpha.
+fn rgba<'host>(value: espy::Value<'host>) -> Result<[u8; 4], espy::Error<'host>> {
+    Ok(color::unpack(value.into_i64()?))
+}
+
 extern_impl! {
     #[espy(debug = "psybeam.color module")]
     struct ColorLib {
         hex: espy::Function::borrow(&ColorHexFn),
+        rgb: espy::Function::borrow(&ColorRgbFn),
+        rgba: espy::Function::borrow(&ColorRgbaFn),
+        hsl: espy::Function::borrow(&ColorHslFn),
+        with_alpha: espy::Function::borrow(&ColorWithAlphaFn),
+        mix: espy::Function::borrow(&ColorMixFn),
+        lighten: espy::Function::borrow(&ColorLightenFn),
+        darken: espy::Function::borrow(&ColorDarkenFn),
+        gradient_at: espy::Function::borrow(&ColorGradientAtFn),
     }
 }
 
+/// Reads a color channel, from 0 to 255.
+fn channel<'host>(value: espy::Value<'host>, name: &str) -> Result<u8, espy::Error<'host>> {
+    let value = value.into_i64()?;
+    u8::try_from(value).map_err(|_| {
+        espy::Error::Other(format!("expected {name} from 0 to 255, found {value}").into())
+    })
+}
+
 extern_impl! {
     #[espy(debug = "psybeam.color.hex function")]
     fn ColorHexFn<'host>(&self, argument) {
         let argument = argument.into_str()?;
-        let argument = argument.strip_prefix('#').unwrap_or(&argument);
-        match argument.len() {
-            6 => Ok(espy::Value::from(
-                (u32::from_str_radix(argument, 16)? << 8) as i64,
-            )),
-            8 => Ok(espy::Value::from(u32::from_str_radix(argument, 16)? as i64)),
-            _ => Err(espy::Error::Other(
-                "expected a string with exactly six characters".into(),
+        match color::hex(&argument) {
+            Some(rgba) => Ok(espy::Value::from(color::pack(rgba))),
+            None => Err(espy::Error::Other(
+                format!("expected a color of six or eight hex digits, such as \"#46c0ea\", found {argument:?}")
+                    .into(),
             )),
         }
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.label_color function")]
-    fn LabelColorFn<'host>(&self, argument) {
-        let text = argument.get(0)?.into_str()?;
-        let [red, green, blue, alpha] = (argument.get(1)?.into_i64()? as u32).to_be_bytes();
-        Ok(espy::Value::owned(Rc::new(Label {
-            text,
-            red,
-            green,
-            blue,
-            alpha,
-        })))
+    #[espy(debug = "psybeam.color.rgb function")]
+    fn ColorRgbFn<'host>(&self, argument) {
+        Ok(espy::Value::from(color::pack([
+            channel(argument.get(0)?, "red")?,
+            channel(argument.get(1)?, "green")?,
+            channel(argument.get(2)?, "blue")?,
+            0xff,
+        ])))
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.widget module")]
-    struct WidgetLib {
-        new: espy::Function::borrow(&WidgetNewFn),
-        refresh: espy::Value::borrow(&WidgetRefreshLib),
-        spacer: espy::Value::borrow(&Widget::Spacer),
+    #[espy(debug = "psybeam.color.rgba function")]
+    fn ColorRgbaFn<'host>(&self, argument) {
+        Ok(espy::Value::from(color::pack([
+            channel(argument.get(0)?, "red")?,
+            channel(argument.get(1)?, "green")?,
+            channel(argument.get(2)?, "blue")?,
+            channel(argument.get(3)?, "alpha")?,
+        ])))
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.widget.refresh module")]
-    struct WidgetRefreshLib {
-        timer: espy::Value::borrow(&WidgetRefreshTimerLib),
+    #[espy(debug = "psybeam.color.hsl function")]
+    fn ColorHslFn<'host>(&self, argument) {
+        // Hue is in degrees, and saturation and lightness are percentages.
+        let hue = argument.get(0)?.into_i64()? as f64;
+        let saturation = argument.get(1)?.into_i64()? as f64 / 100.0;
+        let lightness = argument.get(2)?.into_i64()? as f64 / 100.0;
+        Ok(espy::Value::from(color::pack(color::from_hsl(
+            hue, saturation, lightness, 0xff,
+        ))))
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.widget.refresh.timer module")]
-    struct WidgetRefreshTimerLib {
-        s: espy::Function::borrow(&WidgetRefreshTimerSecondsFn),
-        ms: espy::Function::borrow(&WidgetRefreshTimerMillisecondsFn),
+    #[espy(debug = "psybeam.color.with_alpha function")]
+    fn ColorWithAlphaFn<'host>(&self, argument) {
+        let [red, green, blue, _] = rgba(argument.get(0)?)?;
+        let alpha = channel(argument.get(1)?, "alpha")?;
+        Ok(espy::Value::from(color::pack([red, green, blue, alpha])))
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.widget.refresh.timer.seconds function")]
-    fn WidgetRefreshTimerSecondsFn<'host>(&self, argument) {
-        Ok(espy::Value::owned(Rc::new(WidgetRefreshRate::Timer(
-            Duration::from_secs(argument.into_i64()?.try_into()?),
+    #[espy(debug = "psybeam.color.mix function")]
+    fn ColorMixFn<'host>(&self, argument) {
+        // The weight is the percentage of the second color.
+        let weight = argument.get(2)?.into_i64()? as f64 / 100.0;
+        Ok(espy::Value::from(color::pack(color::mix(
+            rgba(argument.get(0)?)?,
+            rgba(argument.get(1)?)?,
+            weight,
         ))))
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.widget.refresh.timer.milliseconds function")]
-    fn WidgetRefreshTimerMillisecondsFn<'host>(&self, argument) {
-        Ok(espy::Value::owned(Rc::new(WidgetRefreshRate::Timer(
-            Duration::from_millis(argument.into_i64()?.try_into()?),
+    #[espy(debug = "psybeam.color.lighten function")]
+    fn ColorLightenFn<'host>(&self, argument) {
+        // The amount is in percentage points of lightness.
+        let amount = argument.get(1)?.into_i64()? as f64 / 100.0;
+        Ok(espy::Value::from(color::pack(color::lighten(
+            rgba(argument.get(0)?)?,
+            amount,
         ))))
     }
 }
 
 extern_impl! {
-    #[espy(debug = "psybeam.widget.new function")]
-    fn WidgetNewFn<'host>(&self, argument) {
-        let title = argument.find("title".into())?.into_str()?;
-        let width = argument.find("width".into())?.into_i64()?.try_into()?;
-        let refresh = argument
-            .find("refresh".into())?
-            .downcast_extern::<WidgetRefreshRate>()
-            .ok_or_else(|| espy::Error::Other("expected widget refresh rate".into()))?
-            .clone();
+    #[espy(debug = "psybeam.color.darken function")]
+    fn ColorDarkenFn<'host>(&self, argument) {
+        let amount = argument.get(1)?.into_i64()? as f64 / 100.0;
+        Ok(espy::Value::from(color::pack(color::lighten(
+            rgba(argument.get(0)?)?,
+            -amount,
+        ))))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.color.gradient_at function")]
+    fn ColorGradientAtFn<'host>(&self, argument) {
+        let value = argument.get(0)?.into_i64()?;
+        // Stops are given in the same form as for `psybeam.bar`.
+        let mut stops = argument
+            .get(1)?
+            .into_tuple()?
+            .values()
+            .map(|stop| Ok((stop.get(0)?.into_i64()?, rgba(stop.get(1)?)?)))
+            .collect::<Result<Vec<_>, espy::Error>>()?;
+        stops.sort_by_key(|(position, _)| *position);
+        let rgba = color::gradient_at(value, &stops)
+            .ok_or_else(|| espy::Error::Other("expected at least one stop".into()))?;
+        Ok(espy::Value::from(color::pack(rgba)))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.label_color function")]
+    fn LabelColorFn<'host>(&self, argument) {
+        let text = argument.get(0)?.into_str()?;
+        let [red, green, blue, alpha] = (argument.get(1)?.into_i64()? as u32).to_be_bytes();
+        Ok(espy::Value::owned(Rc::new(Label {
+            text,
+            red,
+            green,
+            blue,
+            alpha,
+        })))
+    }
+}
+
+/// Reads the size and position arguments shared by `psybeam.image` and `psybeam.icon`.
+fn image<'host>(
+    source: ImageSource,
+    argument: espy::Value<'host>,
+) -> Result<espy::Value<'host>, espy::Error<'host>> {
+    let size = argument.find("size".into())?.into_i64()?.try_into()?;
+    let argument = argument.into_tuple()?;
+    let x = argument
+        .find_value("x")
+        .cloned()
+        .map_or(Ok(0), |x| x.into_i64())?
+        .try_into()?;
+    let y = argument
+        .find_value("y")
+        .cloned()
+        .map(|y| y.into_i64())
+        .transpose()?
+        .map(i32::try_from)
+        .transpose()?;
+    Ok(espy::Value::owned(Rc::new(Image { source, size, x, y })))
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.image function")]
+    fn ImageFn<'host>(&self, argument) {
+        let path = argument.find("path".into())?.into_str()?;
+        image(ImageSource::Path(path), argument)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.icon function")]
+    fn IconFn<'host>(&self, argument) {
+        let name = argument.find("name".into())?.into_str()?;
+        image(ImageSource::Icon(name), argument)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path module")]
+    struct PathLib {
+        move_to: espy::Function::borrow(&PathMoveToFn),
+        line_to: espy::Function::borrow(&PathLineToFn),
+        arc: espy::Function::borrow(&PathArcFn),
+        close: espy::Function::borrow(&PathCloseFn),
+        stroke: espy::Function::borrow(&PathStrokeFn),
+        fill: espy::Function::borrow(&PathFillFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path.move_to function")]
+    fn PathMoveToFn<'host>(&self, argument) {
+        Ok(espy::Value::owned(Rc::new(PathSegment::MoveTo {
+            x: argument.get(0)?.into_i64()?.try_into()?,
+            y: argument.get(1)?.into_i64()?.try_into()?,
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path.line_to function")]
+    fn PathLineToFn<'host>(&self, argument) {
+        Ok(espy::Value::owned(Rc::new(PathSegment::LineTo {
+            x: argument.get(0)?.into_i64()?.try_into()?,
+            y: argument.get(1)?.into_i64()?.try_into()?,
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path.arc function")]
+    fn PathArcFn<'host>(&self, argument) {
+        Ok(espy::Value::owned(Rc::new(PathSegment::Arc {
+            x: argument.find("x".into())?.into_i64()?.try_into()?,
+            y: argument.find("y".into())?.into_i64()?.try_into()?,
+            radius: argument.find("radius".into())?.into_i64()?.try_into()?,
+            start: argument.find("start".into())?.into_i64()?.try_into()?,
+            end: argument.find("end".into())?.into_i64()?.try_into()?,
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path.close function")]
+    fn PathCloseFn<'host>(&self, _argument) {
+        Ok(espy::Value::owned(Rc::new(PathSegment::Close)))
+    }
+}
+
+/// Reads the segments and color arguments shared by `psybeam.path.stroke` and `psybeam.path.fill`.
+fn path<'host>(
+    paint: PathPaint,
+    argument: &espy::Value<'host>,
+) -> Result<espy::Value<'host>, espy::Error<'host>> {
+    let segment = |value: &espy::Value<'host>| {
+        value
+            .downcast_extern::<PathSegment>()
+            .copied()
+            .ok_or_else(|| espy::Error::Other("expected path segment".into()))
+    };
+    let segments = match argument.find("segments".into())? {
+        espy::Value::Tuple(segments) => segments.values().map(segment).collect(),
+        segment_value => segment(&segment_value).map(|segment| [segment].into()),
+    }?;
+    let color = rgba(argument.find("color".into())?)?;
+    Ok(espy::Value::owned(Rc::new(Path {
+        segments,
+        paint,
+        color,
+    })))
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path.stroke function")]
+    fn PathStrokeFn<'host>(&self, argument) {
+        let width = argument
+            .clone()
+            .into_tuple()?
+            .find_value("width")
+            .cloned()
+            .map_or(Ok(1), |width| width.into_i64())?
+            .try_into()?;
+        path(PathPaint::Stroke { width }, &argument)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.path.fill function")]
+    fn PathFillFn<'host>(&self, argument) {
+        path(PathPaint::Fill, &argument)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.history module")]
+    struct HistoryLib {
+        push: espy::Function::borrow(&HistoryPushFn),
+        get: espy::Function::borrow(&HistoryGetFn),
+    }
+}
+
+/// The widget being drawn, for bindings that keep per-widget state.
+fn drawing<'host>() -> Result<UserWidget, espy::Error<'host>> {
+    render::drawing().ok_or_else(|| espy::Error::Other("not drawing a widget".into()))
+}
+
+fn history<'host>(widget: &UserWidget) -> espy::Value<'host> {
+    espy::Value::owned(Rc::new(History {
+        samples: widget.state.history.borrow().iter().copied().collect(),
+        capacity: widget.width as usize,
+    }))
+}
+
+/// Appends a sample to the widget's history.
+///
+/// One sample is recorded per draw of the widget rather than per refresh period,
+/// so widgets that are redrawn early record more than one sample in that period.
+/// The exception is redrawing to show the output of a command that just finished,
+/// which replaces the sample recorded by the draw that started it.
+fn push_history(widget: &UserWidget, sample: i64) {
+    let state = &widget.state;
+    let mut history = state.history.borrow_mut();
+    if state.history_cycle.replace(state.cycle.get()) == state.cycle.get() {
+        history.pop_back();
+    }
+    // A graph can't show more than one sample per column,
+    // so there's no reason to keep any more than that.
+    while history.len() >= widget.width as usize {
+        history.pop_front();
+    }
+    history.push_back(sample);
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.history.push function")]
+    fn HistoryPushFn<'host>(&self, argument) {
+        let widget = drawing()?;
+        push_history(&widget, argument.into_i64()?);
+        Ok(history(&widget))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.history.get function")]
+    fn HistoryGetFn<'host>(&self, _argument) {
+        Ok(history(&drawing()?))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.graph function")]
+    fn GraphFn<'host>(&self, argument) {
+        let history = argument.find("history".into())?;
+        let history = history
+            .downcast_extern::<History>()
+            .ok_or_else(|| espy::Error::Other("expected history".into()))?;
+        let color = rgba(argument.find("color".into())?)?;
+        let argument = argument.into_tuple()?;
+        let style = match argument
+            .find_value("style")
+            .cloned()
+            .map(|style| style.into_str())
+            .transpose()?
+            .as_deref()
+        {
+            None | Some("line") => GraphStyle::Line,
+            Some("area") => GraphStyle::Area,
+            Some("bar") => GraphStyle::Bar,
+            Some(style) => {
+                return Err(espy::Error::Other(
+                    format!("unknown graph style {style:?} (expected line, area, or bar)")
+                        .into(),
+                ));
+            }
+        };
+        let min = argument
+            .find_value("min")
+            .cloned()
+            .map_or(Ok(0), |min| min.into_i64())?;
+        let max = argument
+            .find_value("max")
+            .cloned()
+            .map(|max| max.into_i64())
+            .transpose()?;
+        let background = argument
+            .find_value("background")
+            .cloned()
+            .map(rgba)
+            .transpose()?;
+        Ok(espy::Value::owned(Rc::new(Graph {
+            samples: history.samples.clone(),
+            capacity: history.capacity,
+            style,
+            min,
+            max,
+            color,
+            background,
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.bar function")]
+    fn BarFn<'host>(&self, argument) {
+        let value = argument.find("value".into())?.into_i64()?;
+        let color = rgba(argument.find("color".into())?)?;
+        let argument = argument.into_tuple()?;
+        let min = argument
+            .find_value("min")
+            .cloned()
+            .map_or(Ok(0), |min| min.into_i64())?;
+        let max = argument
+            .find_value("max")
+            .cloned()
+            .map_or(Ok(100), |max| max.into_i64())?;
+        let direction = match argument
+            .find_value("direction")
+            .cloned()
+            .map(|direction| direction.into_str())
+            .transpose()?
+            .as_deref()
+        {
+            None | Some("horizontal") => BarDirection::Horizontal,
+            Some("vertical") => BarDirection::Vertical,
+            Some(direction) => {
+                return Err(espy::Error::Other(
+                    format!("unknown bar direction {direction:?} (expected horizontal or vertical)")
+                        .into(),
+                ));
+            }
+        };
+        let background = argument
+            .find_value("background")
+            .cloned()
+            .map(rgba)
+            .transpose()?;
+        let mut stops = match argument.find_value("stops").cloned() {
+            Some(stops) => stops
+                .into_tuple()?
+                .values()
+                .map(|stop| Ok((stop.get(0)?.into_i64()?, rgba(stop.get(1)?)?)))
+                .collect::<Result<Vec<_>, espy::Error>>()?,
+            None => Vec::new(),
+        };
+        stops.sort_by_key(|(threshold, _)| *threshold);
+        let x = argument
+            .find_value("x")
+            .cloned()
+            .map_or(Ok(0), |x| x.into_i64())?
+            .try_into()?;
+        let y = argument
+            .find_value("y")
+            .cloned()
+            .map_or(Ok(0), |y| y.into_i64())?
+            .try_into()?;
+        let width = argument
+            .find_value("width")
+            .cloned()
+            .map(|width| width.into_i64())
+            .transpose()?
+            .map(u32::try_from)
+            .transpose()?;
+        let height = argument
+            .find_value("height")
+            .cloned()
+            .map(|height| height.into_i64())
+            .transpose()?
+            .map(u32::try_from)
+            .transpose()?;
+        Ok(espy::Value::owned(Rc::new(Bar {
+            value,
+            min,
+            max,
+            direction,
+            color,
+            background,
+            stops: stops.into(),
+            x,
+            y,
+            width,
+            height,
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget module")]
+    struct WidgetLib {
+        new: espy::Function::borrow(&WidgetNewFn),
+        frame_time: espy::Function::borrow(&WidgetFrameTimeFn),
+        refresh: espy::Value::borrow(&WidgetRefreshLib),
+        spacer: espy::Value::borrow(&Widget::Spacer),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.frame_time function")]
+    fn WidgetFrameTimeFn<'host>(&self, _argument) {
+        // Milliseconds with an unspecified base, so only differences between frames are meaningful.
+        Ok(espy::Value::I64(drawing()?.state.frame_time.get().into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh module")]
+    struct WidgetRefreshLib {
+        timer: espy::Value::borrow(&WidgetRefreshTimerLib),
+        frame: espy::Value::borrow(&WidgetRefreshRate::Framerate),
+        stream: espy::Function::borrow(&WidgetRefreshStreamFn),
+        watch: espy::Function::borrow(&WidgetRefreshWatchFn),
+        aligned: espy::Function::borrow(&WidgetRefreshAlignedFn),
+        every_minute: espy::Value::borrow(&WidgetRefreshRate::Aligned(Duration::from_secs(60))),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh.timer module")]
+    struct WidgetRefreshTimerLib {
+        s: espy::Function::borrow(&WidgetRefreshTimerSecondsFn),
+        ms: espy::Function::borrow(&WidgetRefreshTimerMillisecondsFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh.timer.seconds function")]
+    fn WidgetRefreshTimerSecondsFn<'host>(&self, argument) {
+        Ok(espy::Value::owned(Rc::new(WidgetRefreshRate::Timer(
+            Duration::from_secs(argument.into_i64()?.try_into()?),
+        ))))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh.timer.milliseconds function")]
+    fn WidgetRefreshTimerMillisecondsFn<'host>(&self, argument) {
+        Ok(espy::Value::owned(Rc::new(WidgetRefreshRate::Timer(
+            Duration::from_millis(argument.into_i64()?.try_into()?),
+        ))))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh.stream function")]
+    fn WidgetRefreshStreamFn<'host>(&self, argument) {
+        Ok(espy::Value::owned(Rc::new(WidgetRefreshRate::Stream(
+            argv(argument)?,
+        ))))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh.watch function")]
+    fn WidgetRefreshWatchFn<'host>(&self, argument) {
+        // Either a path, or a named `path` alongside a `fallback` timer.
+        let (path, fallback) = match &argument {
+            espy::Value::Tuple(tuple) if let Some(path) = tuple.find_value("path") => (
+                path.clone(),
+                tuple.find_value("fallback").cloned(),
+            ),
+            _ => (argument, None),
+        };
+        let fallback = fallback
+            .map(|fallback| match fallback.downcast_extern::<WidgetRefreshRate>() {
+                Some(WidgetRefreshRate::Timer(duration)) => Ok(*duration),
+                _ => Err(espy::Error::Other("expected a timer as the fallback".into())),
+            })
+            .transpose()?;
+        Ok(espy::Value::owned(Rc::new(WidgetRefreshRate::Watch {
+            path: path.into_str()?,
+            fallback,
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.refresh.aligned function")]
+    fn WidgetRefreshAlignedFn<'host>(&self, argument) {
+        match argument.downcast_extern::<WidgetRefreshRate>() {
+            Some(WidgetRefreshRate::Timer(period)) if !period.is_zero() => Ok(espy::Value::owned(
+                Rc::new(WidgetRefreshRate::Aligned(*period)),
+            )),
+            _ => Err(espy::Error::Other("expected a nonzero timer to align".into())),
+        }
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.stream module")]
+    struct StreamLib {
+        line: espy::Function::borrow(&StreamLineFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.stream.line function")]
+    fn StreamLineFn<'host>(&self, _argument) {
+        // Empty until the stream prints its first line.
+        let line = drawing()?.state.line.borrow().clone();
+        Ok(espy::Value::String(line.unwrap_or_else(|| "".into())))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.widget.new function")]
+    fn WidgetNewFn<'host>(&self, argument) {
+        let title = argument.find("title".into())?.into_str()?;
+        let width = argument.find("width".into())?.into_i64()?.try_into()?;
+        let refresh = argument
+            .find("refresh".into())?
+            .downcast_extern::<WidgetRefreshRate>()
+            .ok_or_else(|| espy::Error::Other("expected widget refresh rate".into()))?
+            .clone();
+        let signal = argument
+            .clone()
+            .into_tuple()?
+            .find_value("signal")
+            .cloned()
+            .map(|signal| signal.into_i64())
+            .transpose()?;
         let draw = argument
             .find("draw".into())?
             .into_function()?
@@ -250,7 +1172,903 @@ extern_impl! {
             title,
             width,
             refresh,
+            signal,
             draw,
+            state: Rc::new(WidgetState::default()),
         }))))
     }
 }
+
+extern_impl! {
+    #[espy(debug = "psybeam.time module")]
+    struct TimeLib {
+        now: espy::Function::borrow(&TimeNowFn),
+        format: espy::Function::borrow(&TimeFormatFn),
+        in_zone: espy::Function::borrow(&TimeInZoneFn),
+        timestamp: espy::Function::borrow(&TimeTimestampFn),
+    }
+}
+
+fn time<'a, 'host>(value: &'a espy::Value<'host>) -> Result<&'a Time, espy::Error<'host>> {
+    value
+        .downcast_extern::<Time>()
+        .ok_or_else(|| espy::Error::Other("expected time".into()))
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.time.now function")]
+    fn TimeNowFn<'host>(&self, _argument) {
+        // The local time zone comes from $TZ or /etc/localtime.
+        Ok(espy::Value::owned(Rc::new(Time(jiff::Zoned::now()))))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.time.format function")]
+    fn TimeFormatFn<'host>(&self, argument) {
+        let value = argument.get(0)?;
+        let format = argument.get(1)?.into_str()?;
+        // strftime-style directives, such as "%a %d %b %H:%M".
+        let text = jiff::fmt::strtime::format(&*format, &time(&value)?.0)
+            .map_err(|e| espy::Error::Other(e.into()))?;
+        Ok(espy::Value::String(text.into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.time.in_zone function")]
+    fn TimeInZoneFn<'host>(&self, argument) {
+        let value = argument.get(0)?;
+        // An IANA time zone name, such as "Europe/Berlin".
+        let zone = argument.get(1)?.into_str()?;
+        let time = time(&value)?
+            .0
+            .in_tz(&zone)
+            .map_err(|e| espy::Error::Other(e.into()))?;
+        Ok(espy::Value::owned(Rc::new(Time(time))))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.time.timestamp function")]
+    fn TimeTimestampFn<'host>(&self, argument) {
+        // Seconds since the Unix epoch.
+        Ok(espy::Value::I64(time(&argument)?.0.timestamp().as_second()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.json module")]
+    struct JsonLib {
+        parse: espy::Function::borrow(&JsonParseFn),
+        encode: espy::Function::borrow(&JsonEncodeFn),
+    }
+}
+
+/// Converts parsed JSON into espy values.
+///
+/// null becomes unit, arrays become tuples, and objects become named tuples.
+/// Empty arrays and objects both become an empty tuple, which is encoded as an empty array.
+fn json_value<'host>(json: json::Json) -> espy::Value<'host> {
+    match json {
+        json::Json::Null => espy::Value::Unit,
+        json::Json::Bool(value) => espy::Value::from(value),
+        json::Json::Integer(value) => espy::Value::I64(value),
+        json::Json::Number(value) | json::Json::String(value) => espy::Value::String(value),
+        json::Json::Array(values) => espy::Value::Tuple(
+            values
+                .into_iter()
+                .map(json_value)
+                .collect::<Rc<[_]>>()
+                .into(),
+        ),
+        json::Json::Object(members) => espy::Value::Tuple(
+            members
+                .into_iter()
+                .map(|(key, value)| (key, json_value(value)))
+                .collect::<Rc<[_]>>()
+                .into(),
+        ),
+    }
+}
+
+/// The inverse of `json_value`:
+/// tuples whose fields are all named are encoded as objects, and other tuples as arrays.
+fn encode_json<'host>(
+    output: &mut String,
+    value: espy::Value<'host>,
+) -> Result<(), espy::Error<'host>> {
+    match value {
+        espy::Value::Unit => output.push_str("null"),
+        espy::Value::Bool(value) => output.push_str(if value { "true" } else { "false" }),
+        espy::Value::I64(value) => output.push_str(&value.to_string()),
+        espy::Value::String(value) => json::encode_string(output, &value),
+        espy::Value::Tuple(tuple) => {
+            let object = tuple.len() > 0 && tuple.iter().all(|(name, _)| name.is_some());
+            output.push(if object { '{' } else { '[' });
+            for (i, (name, value)) in tuple.iter().enumerate() {
+                if i > 0 {
+                    output.push(',');
+                }
+                if let Some(name) = name.filter(|_| object) {
+                    json::encode_string(output, name);
+                    output.push(':');
+                }
+                encode_json(output, value.clone())?;
+            }
+            output.push(if object { '}' } else { ']' });
+        }
+        _ => {
+            return Err(espy::Error::Other(
+                "only units, booleans, integers, strings, and tuples can be encoded".into(),
+            ));
+        }
+    }
+    Ok(())
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.json.parse function")]
+    fn JsonParseFn<'host>(&self, argument) {
+        let json = json::parse(&argument.into_str()?).map_err(|e| espy::Error::Other(e.into()))?;
+        Ok(json_value(json))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.json.encode function")]
+    fn JsonEncodeFn<'host>(&self, argument) {
+        let mut output = String::new();
+        encode_json(&mut output, argument)?;
+        Ok(espy::Value::String(output.into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys module")]
+    struct SysLib {
+        cpu: espy::Function::borrow(&SysCpuFn),
+        memory: espy::Function::borrow(&SysMemoryFn),
+        load: espy::Function::borrow(&SysLoadFn),
+        uptime: espy::Function::borrow(&SysUptimeFn),
+        sensors: espy::Function::borrow(&SysSensorsFn),
+        temperature: espy::Function::borrow(&SysTemperatureFn),
+    }
+}
+
+fn cpu<'host>(cpu: &sys::Cpu) -> espy::Value<'host> {
+    let times = cpu.times;
+    espy::Value::Tuple(
+        [
+            (Rc::from("usage"), espy::Value::I64(cpu.usage)),
+            (Rc::from("user"), espy::Value::I64(times.user)),
+            (Rc::from("nice"), espy::Value::I64(times.nice)),
+            (Rc::from("system"), espy::Value::I64(times.system)),
+            (Rc::from("idle"), espy::Value::I64(times.idle)),
+            (Rc::from("iowait"), espy::Value::I64(times.iowait)),
+            (Rc::from("irq"), espy::Value::I64(times.irq)),
+            (Rc::from("softirq"), espy::Value::I64(times.softirq)),
+            (Rc::from("steal"), espy::Value::I64(times.steal)),
+            (Rc::from("total"), espy::Value::I64(times.total())),
+        ]
+        .into(),
+    )
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys.cpu function")]
+    fn SysCpuFn<'host>(&self, _argument) {
+        let mut cpus = sys::cpu()?;
+        // Usage is measured since the widget's previous cycle, or since boot outside of one.
+        if let Some(widget) = render::drawing() {
+            let state = widget.state;
+            let last = state
+                .cpu_times
+                .borrow_mut()
+                .record(state.cycle.get(), cpus.times());
+            if let Some(last) = last {
+                cpus.measure(&last);
+            }
+        }
+        Ok(espy::Value::Tuple(
+            [
+                (Rc::from("total"), cpu(&cpus.total)),
+                (
+                    Rc::from("cores"),
+                    espy::Value::Tuple(cpus.cores.iter().map(cpu).collect::<Rc<[_]>>().into()),
+                ),
+                (Rc::from("count"), espy::Value::I64(cpus.cores.len() as i64)),
+            ]
+            .into(),
+        ))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys.memory function")]
+    fn SysMemoryFn<'host>(&self, _argument) {
+        let memory = sys::memory()?;
+        Ok(espy::Value::Tuple(
+            [
+                (Rc::from("total"), espy::Value::I64(memory.total)),
+                (Rc::from("free"), espy::Value::I64(memory.free)),
+                (Rc::from("available"), espy::Value::I64(memory.available)),
+                (Rc::from("used"), espy::Value::I64(memory.total - memory.available)),
+                (Rc::from("buffers"), espy::Value::I64(memory.buffers)),
+                (Rc::from("cached"), espy::Value::I64(memory.cached)),
+                (Rc::from("swap_total"), espy::Value::I64(memory.swap_total)),
+                (Rc::from("swap_free"), espy::Value::I64(memory.swap_free)),
+            ]
+            .into(),
+        ))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys.load function")]
+    fn SysLoadFn<'host>(&self, _argument) {
+        let load = sys::load()?;
+        Ok(espy::Value::Tuple(
+            [
+                (Rc::from("one"), espy::Value::I64(load.one)),
+                (Rc::from("five"), espy::Value::I64(load.five)),
+                (Rc::from("fifteen"), espy::Value::I64(load.fifteen)),
+                (Rc::from("running"), espy::Value::I64(load.running)),
+                (Rc::from("threads"), espy::Value::I64(load.threads)),
+            ]
+            .into(),
+        ))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys.uptime function")]
+    fn SysUptimeFn<'host>(&self, _argument) {
+        Ok(espy::Value::I64(sys::uptime()?))
+    }
+}
+
+/// Missing thresholds are represented by unit.
+fn temperature<'host>(chip: &sys::Chip, temperature: &sys::Temperature) -> espy::Value<'host> {
+    let optional = |value: Option<i64>| value.map_or(espy::Value::Unit, espy::Value::I64);
+    espy::Value::Tuple(
+        [
+            (Rc::from("chip"), espy::Value::String(chip.name.clone())),
+            (
+                Rc::from("label"),
+                espy::Value::String(temperature.label.clone()),
+            ),
+            (
+                Rc::from("temperature"),
+                espy::Value::I64(temperature.temperature),
+            ),
+            (Rc::from("max"), optional(temperature.max)),
+            (Rc::from("critical"), optional(temperature.critical)),
+        ]
+        .into(),
+    )
+}
+
+/// Empty lists are represented by unit.
+fn list<'host>(values: impl ExactSizeIterator<Item = espy::Value<'host>>) -> espy::Value<'host> {
+    if values.len() == 0 {
+        espy::Value::Unit
+    } else {
+        espy::Value::Tuple(values.collect::<Rc<[_]>>().into())
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys.sensors function")]
+    fn SysSensorsFn<'host>(&self, _argument) {
+        let chips = sys::sensors()?;
+        Ok(list(chips.iter().map(|chip| {
+            let fans = chip.fans.iter().map(|fan| {
+                espy::Value::Tuple(
+                    [
+                        (Rc::from("label"), espy::Value::String(fan.label.clone())),
+                        (Rc::from("rpm"), espy::Value::I64(fan.rpm)),
+                    ]
+                    .into(),
+                )
+            });
+            espy::Value::Tuple(
+                [
+                    (Rc::from("name"), espy::Value::String(chip.name.clone())),
+                    (
+                        Rc::from("temperatures"),
+                        list(chip.temperatures.iter().map(|t| temperature(chip, t))),
+                    ),
+                    (Rc::from("fans"), list(fans)),
+                ]
+                .into(),
+            )
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.sys.temperature function")]
+    fn SysTemperatureFn<'host>(&self, argument) {
+        let label = argument.into_str()?;
+        let chips = sys::sensors()?;
+        chips
+            .iter()
+            .find_map(|chip| {
+                let found = chip.temperatures.iter().find(|t| t.label == label)?;
+                Some(temperature(chip, found))
+            })
+            .ok_or_else(|| espy::Error::Other(format!("no temperature sensor labelled {label}").into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.power module")]
+    struct PowerLib {
+        batteries: espy::Function::borrow(&PowerBatteriesFn),
+        battery: espy::Function::borrow(&PowerBatteryFn),
+        ac_online: espy::Function::borrow(&PowerAcOnlineFn),
+    }
+}
+
+/// Unknown quantities are represented by unit.
+fn battery<'host>(battery: &power::Battery) -> espy::Value<'host> {
+    let optional = |value: Option<i64>| value.map_or(espy::Value::Unit, espy::Value::I64);
+    espy::Value::Tuple(
+        [
+            (Rc::from("name"), espy::Value::String(battery.name.clone())),
+            (Rc::from("capacity"), optional(battery.capacity)),
+            (
+                Rc::from("status"),
+                espy::Value::String(battery.status.clone()),
+            ),
+            (Rc::from("energy_now"), optional(battery.energy_now)),
+            (Rc::from("energy_full"), optional(battery.energy_full)),
+            (Rc::from("power"), optional(battery.power)),
+            (Rc::from("time_to_empty"), optional(battery.time_to_empty())),
+            (Rc::from("time_to_full"), optional(battery.time_to_full())),
+        ]
+        .into(),
+    )
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.power.batteries function")]
+    fn PowerBatteriesFn<'host>(&self, _argument) {
+        let batteries = power::batteries()?;
+        Ok(if batteries.is_empty() {
+            espy::Value::Unit
+        } else {
+            espy::Value::Tuple(batteries.iter().map(battery).collect::<Rc<[_]>>().into())
+        })
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.power.battery function")]
+    fn PowerBatteryFn<'host>(&self, argument) {
+        Ok(battery(&power::battery(&argument.into_str()?)?))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.power.ac_online function")]
+    fn PowerAcOnlineFn<'host>(&self, _argument) {
+        Ok(espy::Value::from(power::ac_online()?))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.net module")]
+    struct NetLib {
+        interfaces: espy::Function::borrow(&NetInterfacesFn),
+        interface: espy::Function::borrow(&NetInterfaceFn),
+        wireless: espy::Function::borrow(&NetWirelessFn),
+    }
+}
+
+/// Measures throughput since the widget's previous cycle, or not at all outside of one.
+fn measure(mut interface: net::Interface) -> net::Interface {
+    if let Some(widget) = render::drawing() {
+        let state = widget.state;
+        let last = state
+            .net_samples
+            .borrow_mut()
+            .entry(interface.name.clone())
+            .or_default()
+            .record(state.cycle.get(), interface.sample());
+        if let Some(last) = last {
+            interface.measure(&last);
+        }
+    }
+    interface
+}
+
+/// Missing hardware addresses and empty address lists are represented by unit.
+fn interface<'host>(interface: &net::Interface) -> espy::Value<'host> {
+    let addresses = if interface.addresses.is_empty() {
+        espy::Value::Unit
+    } else {
+        espy::Value::Tuple(
+            interface
+                .addresses
+                .iter()
+                .map(|address| espy::Value::String(address.clone()))
+                .collect::<Rc<[_]>>()
+                .into(),
+        )
+    };
+    espy::Value::Tuple(
+        [
+            (
+                Rc::from("name"),
+                espy::Value::String(interface.name.clone()),
+            ),
+            (
+                Rc::from("state"),
+                espy::Value::String(interface.state.clone()),
+            ),
+            (
+                Rc::from("mac"),
+                interface
+                    .mac
+                    .clone()
+                    .map_or(espy::Value::Unit, espy::Value::String),
+            ),
+            (Rc::from("wireless"), espy::Value::from(interface.wireless)),
+            (Rc::from("addresses"), addresses),
+            (Rc::from("rx_bytes"), espy::Value::I64(interface.rx_bytes)),
+            (Rc::from("tx_bytes"), espy::Value::I64(interface.tx_bytes)),
+            (Rc::from("rx_rate"), espy::Value::I64(interface.rx_rate)),
+            (Rc::from("tx_rate"), espy::Value::I64(interface.tx_rate)),
+        ]
+        .into(),
+    )
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.net.interfaces function")]
+    fn NetInterfacesFn<'host>(&self, _argument) {
+        let interfaces = net::interfaces()?;
+        Ok(if interfaces.is_empty() {
+            espy::Value::Unit
+        } else {
+            espy::Value::Tuple(
+                interfaces
+                    .into_iter()
+                    .map(measure)
+                    .map(|measured| interface(&measured))
+                    .collect::<Rc<[_]>>()
+                    .into(),
+            )
+        })
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.net.interface function")]
+    fn NetInterfaceFn<'host>(&self, argument) {
+        Ok(interface(&measure(net::interface(&argument.into_str()?)?)))
+    }
+}
+
+/// Unknown quantities, and every detail of a disconnected interface, are represented by unit.
+fn wireless<'host>(wireless: &net::Wireless) -> espy::Value<'host> {
+    let optional = |value: Option<i64>| value.map_or(espy::Value::Unit, espy::Value::I64);
+    let string =
+        |value: &Option<Rc<str>>| value.clone().map_or(espy::Value::Unit, espy::Value::String);
+    espy::Value::Tuple(
+        [
+            (
+                Rc::from("interface"),
+                espy::Value::String(wireless.interface.clone()),
+            ),
+            (
+                Rc::from("connected"),
+                espy::Value::from(wireless.ssid.is_some()),
+            ),
+            (Rc::from("ssid"), string(&wireless.ssid)),
+            (Rc::from("bssid"), string(&wireless.bssid)),
+            (Rc::from("signal"), optional(wireless.signal)),
+            (Rc::from("quality"), optional(wireless.quality())),
+            (Rc::from("bitrate"), optional(wireless.bitrate)),
+            (Rc::from("frequency"), optional(wireless.frequency)),
+        ]
+        .into(),
+    )
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.net.wireless function")]
+    fn NetWirelessFn<'host>(&self, argument) {
+        let interfaces = net::wireless()?;
+        // Given an interface name, return only that interface.
+        if let espy::Value::String(name) = &argument {
+            return match interfaces.iter().find(|wireless| wireless.interface == *name) {
+                Some(found) => Ok(wireless(found)),
+                None => Err(espy::Error::Other(
+                    format!("{name} is not a wireless interface").into(),
+                )),
+            };
+        }
+        Ok(if interfaces.is_empty() {
+            espy::Value::Unit
+        } else {
+            espy::Value::Tuple(interfaces.iter().map(wireless).collect::<Rc<[_]>>().into())
+        })
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.fs module")]
+    struct FsLib {
+        usage: espy::Function::borrow(&FsUsageFn),
+        mounts: espy::Function::borrow(&FsMountsFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.fs.usage function")]
+    fn FsUsageFn<'host>(&self, argument) {
+        let usage = filesystem::usage(&argument.into_str()?)?;
+        Ok(espy::Value::Tuple(
+            [
+                (Rc::from("total"), espy::Value::I64(usage.total)),
+                (Rc::from("used"), espy::Value::I64(usage.used())),
+                (Rc::from("free"), espy::Value::I64(usage.free)),
+                (Rc::from("available"), espy::Value::I64(usage.available)),
+                (Rc::from("inodes"), espy::Value::I64(usage.inodes)),
+                (
+                    Rc::from("inodes_used"),
+                    espy::Value::I64(usage.inodes - usage.inodes_free),
+                ),
+                (Rc::from("inodes_free"), espy::Value::I64(usage.inodes_free)),
+            ]
+            .into(),
+        ))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.fs.mounts function")]
+    fn FsMountsFn<'host>(&self, _argument) {
+        let mounts = filesystem::mounts()?;
+        Ok(list(mounts.iter().map(|mount| {
+            espy::Value::Tuple(
+                [
+                    (Rc::from("source"), espy::Value::String(mount.source.clone())),
+                    (Rc::from("path"), espy::Value::String(mount.path.clone())),
+                    (Rc::from("type"), espy::Value::String(mount.kind.clone())),
+                    (Rc::from("options"), espy::Value::String(mount.options.clone())),
+                    (Rc::from("read_only"), espy::Value::from(mount.read_only())),
+                ]
+                .into(),
+            )
+        })))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.backlight module")]
+    struct BacklightLib {
+        list: espy::Function::borrow(&BacklightListFn),
+        get: espy::Function::borrow(&BacklightGetFn),
+        set: espy::Function::borrow(&BacklightSetFn),
+    }
+}
+
+fn backlight<'host>(backlight: &backlight::Backlight) -> espy::Value<'host> {
+    espy::Value::Tuple(
+        [
+            (
+                Rc::from("name"),
+                espy::Value::String(backlight.name.clone()),
+            ),
+            (
+                Rc::from("type"),
+                espy::Value::String(backlight.kind.clone()),
+            ),
+            (
+                Rc::from("brightness"),
+                espy::Value::I64(backlight.brightness),
+            ),
+            (
+                Rc::from("max_brightness"),
+                espy::Value::I64(backlight.max_brightness),
+            ),
+            (Rc::from("percent"), espy::Value::I64(backlight.percent())),
+        ]
+        .into(),
+    )
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.backlight.list function")]
+    fn BacklightListFn<'host>(&self, _argument) {
+        let backlights = backlight::backlights()?;
+        Ok(list(backlights.iter().map(backlight)))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.backlight.get function")]
+    fn BacklightGetFn<'host>(&self, argument) {
+        Ok(backlight(&backlight::backlight(&argument.into_str()?)?))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.backlight.set function")]
+    fn BacklightSetFn<'host>(&self, argument) {
+        let name = argument.get(0)?.into_str()?;
+        let brightness = argument.get(1)?.into_i64()?;
+        backlight::set(&name, brightness)?;
+        Ok(espy::Value::Unit)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format module")]
+    struct FormatLib {
+        bytes: espy::Value::borrow(&FormatBytesLib),
+        duration: espy::Function::borrow(&FormatDurationFn),
+        percent: espy::Function::borrow(&FormatPercentFn),
+        pad_left: espy::Function::borrow(&FormatPadLeftFn),
+        pad_right: espy::Function::borrow(&FormatPadRightFn),
+        fmt: espy::Function::borrow(&FormatFmtFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.bytes module")]
+    struct FormatBytesLib {
+        iec: espy::Function::borrow(&FormatBytesIecFn),
+        si: espy::Function::borrow(&FormatBytesSiFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.bytes.iec function")]
+    fn FormatBytesIecFn<'host>(&self, argument) {
+        Ok(espy::Value::String(format::bytes(argument.into_i64()?, false).into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.bytes.si function")]
+    fn FormatBytesSiFn<'host>(&self, argument) {
+        Ok(espy::Value::String(format::bytes(argument.into_i64()?, true).into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.duration function")]
+    fn FormatDurationFn<'host>(&self, argument) {
+        Ok(espy::Value::String(format::duration(argument.into_i64()?).into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.percent function")]
+    fn FormatPercentFn<'host>(&self, argument) {
+        // Either a percentage, or a part and a total.
+        let (part, total) = match argument {
+            espy::Value::Tuple(_) => (argument.get(0)?.into_i64()?, argument.get(1)?.into_i64()?),
+            percent => (percent.into_i64()?, 100),
+        };
+        Ok(espy::Value::String(format::percent(part, total).into()))
+    }
+}
+
+/// Integers are formatted in decimal, and anything else must be a string.
+fn format_argument<'host>(
+    value: espy::Value<'host>,
+) -> Result<format::Argument, espy::Error<'host>> {
+    Ok(match value {
+        espy::Value::I64(integer) => format::Argument::Integer(integer),
+        value => format::Argument::String(value.into_str()?),
+    })
+}
+
+fn pad<'host>(
+    argument: espy::Value<'host>,
+    align: format::Align,
+) -> Result<espy::Value<'host>, espy::Error<'host>> {
+    let text = match format_argument(argument.get(0)?)? {
+        format::Argument::Integer(integer) => integer.to_string(),
+        format::Argument::String(string) => string.to_string(),
+    };
+    let width = argument.get(1)?.into_i64()?.try_into()?;
+    Ok(espy::Value::String(
+        format::pad(&text, width, align, ' ').into(),
+    ))
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.pad_left function")]
+    fn FormatPadLeftFn<'host>(&self, argument) {
+        pad(argument, format::Align::Right)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.pad_right function")]
+    fn FormatPadRightFn<'host>(&self, argument) {
+        pad(argument, format::Align::Left)
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.format.fmt function")]
+    fn FormatFmtFn<'host>(&self, argument) {
+        // Either a template on its own, or a template followed by its arguments.
+        let (template, arguments) = match argument {
+            espy::Value::Tuple(arguments) => {
+                let mut arguments = arguments.values().cloned();
+                let template = arguments
+                    .next()
+                    .ok_or_else(|| espy::Error::Other("expected a template".into()))?
+                    .into_str()?;
+                (
+                    template,
+                    arguments.map(format_argument).collect::<Result<Vec<_>, _>>()?,
+                )
+            }
+            template => (template.into_str()?, Vec::new()),
+        };
+        let output = format::template(&template, &arguments)
+            .map_err(|e| espy::Error::Other(e.into()))?;
+        Ok(espy::Value::String(output.into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.regex module")]
+    struct RegexLib {
+        is_match: espy::Function::borrow(&RegexIsMatchFn),
+        captures: espy::Function::borrow(&RegexCapturesFn),
+        replace_all: espy::Function::borrow(&RegexReplaceAllFn),
+    }
+}
+
+/// Reads the text and pattern that every `psybeam.regex` function starts with.
+fn text_and_pattern<'host>(
+    argument: &espy::Value<'host>,
+) -> Result<(Rc<str>, Rc<regex::Regex>), espy::Error<'host>> {
+    let text = argument.get(0)?.into_str()?;
+    let pattern = pattern::compile(&argument.get(1)?.into_str()?)
+        .map_err(|e| espy::Error::Other(e.into()))?;
+    Ok((text, pattern))
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.regex.is_match function")]
+    fn RegexIsMatchFn<'host>(&self, argument) {
+        let (text, pattern) = text_and_pattern(&argument)?;
+        Ok(espy::Value::from(pattern.is_match(&text)))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.regex.captures function")]
+    fn RegexCapturesFn<'host>(&self, argument) {
+        let (text, pattern) = text_and_pattern(&argument)?;
+        let Some(captures) = pattern.captures(&text) else {
+            return Ok(espy::Value::Unit);
+        };
+        let group = |i: usize| {
+            captures
+                .get(i)
+                .map_or(espy::Value::Unit, |group| espy::Value::String(group.as_str().into()))
+        };
+        // Named groups are returned by name; otherwise, every group is returned in order.
+        // Groups which didn't participate in the match are unit.
+        let named = pattern
+            .capture_names()
+            .enumerate()
+            .filter_map(|(i, name)| Some((Rc::from(name?), group(i))))
+            .collect::<Rc<[_]>>();
+        Ok(if !named.is_empty() {
+            espy::Value::Tuple(named.into())
+        } else if captures.len() > 1 {
+            espy::Value::Tuple((1..captures.len()).map(group).collect::<Rc<[_]>>().into())
+        } else {
+            group(0)
+        })
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.regex.replace_all function")]
+    fn RegexReplaceAllFn<'host>(&self, argument) {
+        let (text, pattern) = text_and_pattern(&argument)?;
+        // The replacement may refer to groups as $1 or ${name}.
+        let replacement = argument.get(2)?.into_str()?;
+        Ok(espy::Value::String(
+            pattern.replace_all(&text, &*replacement).into(),
+        ))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env module")]
+    struct EnvLib {
+        get: espy::Function::borrow(&EnvGetFn),
+        vars: espy::Function::borrow(&EnvVarsFn),
+        hostname: espy::Function::borrow(&EnvHostnameFn),
+        username: espy::Function::borrow(&EnvUsernameFn),
+        uname: espy::Function::borrow(&EnvUnameFn),
+        os_release: espy::Function::borrow(&EnvOsReleaseFn),
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env.get function")]
+    fn EnvGetFn<'host>(&self, argument) {
+        // Unset (and non-unicode) variables are unit.
+        Ok(std::env::var(&*argument.into_str()?)
+            .map_or(espy::Value::Unit, |value| espy::Value::String(value.into())))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env.vars function")]
+    fn EnvVarsFn<'host>(&self, _argument) {
+        // Non-unicode names and values are converted lossily rather than panicking.
+        let mut vars = std::env::vars_os()
+            .map(|(name, value)| {
+                (
+                    Rc::<str>::from(name.to_string_lossy()),
+                    espy::Value::String(value.to_string_lossy().into()),
+                )
+            })
+            .collect::<Vec<_>>();
+        vars.sort_by(|(a, _), (b, _)| a.cmp(b));
+        Ok(espy::Value::Tuple(vars.into_iter().collect::<Rc<[_]>>().into()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env.hostname function")]
+    fn EnvHostnameFn<'host>(&self, _argument) {
+        Ok(espy::Value::String(host::hostname()))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env.username function")]
+    fn EnvUsernameFn<'host>(&self, _argument) {
+        Ok(espy::Value::String(host::username()?))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env.uname function")]
+    fn EnvUnameFn<'host>(&self, _argument) {
+        let uname = host::uname();
+        Ok(espy::Value::Tuple(
+            [
+                (Rc::from("sysname"), espy::Value::String(uname.sysname)),
+                (Rc::from("nodename"), espy::Value::String(uname.nodename)),
+                (Rc::from("release"), espy::Value::String(uname.release)),
+                (Rc::from("version"), espy::Value::String(uname.version)),
+                (Rc::from("machine"), espy::Value::String(uname.machine)),
+            ]
+            .into(),
+        ))
+    }
+}
+
+extern_impl! {
+    #[espy(debug = "psybeam.env.os_release function")]
+    fn EnvOsReleaseFn<'host>(&self, _argument) {
+        // Keys are lowercased, so that PRETTY_NAME is read as `os_release.pretty_name`.
+        Ok(espy::Value::Tuple(
+            host::os_release()?
+                .into_iter()
+                .map(|(key, value)| (Rc::from(key.to_lowercase()), espy::Value::String(value)))
+                .collect::<Rc<[_]>>()
+                .into(),
+        ))
+    }
+}
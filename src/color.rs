@@ -0,0 +1,108 @@
+//! Color arithmetic for `psybeam.color`.
+//!
+//! Colors are non-premultiplied sRGB with straight alpha, as `[red, green, blue, alpha]`.
+//! Scripts see them packed into an integer as 0xRRGGBBAA.
+
+pub type Rgba = [u8; 4];
+
+pub fn pack(color: Rgba) -> i64 {
+    u32::from_be_bytes(color).into()
+}
+
+pub fn unpack(color: i64) -> Rgba {
+    (color as u32).to_be_bytes()
+}
+
+/// Parses "#rrggbb" or "#rrggbbaa", with or without the "#".
+pub fn hex(hex: &str) -> Option<Rgba> {
+    let hex = hex.strip_prefix('#').unwrap_or(hex);
+    // from_str_radix would also accept a leading sign.
+    if !hex.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return None;
+    }
+    let value = u32::from_str_radix(hex, 16).ok()?;
+    match hex.len() {
+        6 => Some((value << 8 | 0xff).to_be_bytes()),
+        8 => Some(value.to_be_bytes()),
+        _ => None,
+    }
+}
+
+fn channel(value: f64) -> u8 {
+    (value * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Converts a hue in degrees, and saturation and lightness from 0 to 1.
+pub fn from_hsl(hue: f64, saturation: f64, lightness: f64, alpha: u8) -> Rgba {
+    let saturation = saturation.clamp(0.0, 1.0);
+    let lightness = lightness.clamp(0.0, 1.0);
+    let chroma = (1.0 - (2.0 * lightness - 1.0).abs()) * saturation;
+    let hue = hue.rem_euclid(360.0) / 60.0;
+    let x = chroma * (1.0 - (hue % 2.0 - 1.0).abs());
+    let (red, green, blue) = match hue as u8 {
+        0 => (chroma, x, 0.0),
+        1 => (x, chroma, 0.0),
+        2 => (0.0, chroma, x),
+        3 => (0.0, x, chroma),
+        4 => (x, 0.0, chroma),
+        _ => (chroma, 0.0, x),
+    };
+    let m = lightness - chroma / 2.0;
+    [
+        channel(red + m),
+        channel(green + m),
+        channel(blue + m),
+        alpha,
+    ]
+}
+
+/// The hue in degrees, and saturation and lightness from 0 to 1.
+pub fn to_hsl([red, green, blue, _]: Rgba) -> (f64, f64, f64) {
+    let [red, green, blue] = [red, green, blue].map(|channel| f64::from(channel) / 255.0);
+    let max = red.max(green).max(blue);
+    let min = red.min(green).min(blue);
+    let chroma = max - min;
+    let lightness = (max + min) / 2.0;
+    if chroma == 0.0 {
+        return (0.0, 0.0, lightness);
+    }
+    let hue = if max == red {
+        ((green - blue) / chroma).rem_euclid(6.0)
+    } else if max == green {
+        (blue - red) / chroma + 2.0
+    } else {
+        (red - green) / chroma + 4.0
+    };
+    let saturation = chroma / (1.0 - (2.0 * lightness - 1.0).abs());
+    (hue * 60.0, saturation, lightness)
+}
+
+/// Moves the lightness of `color` by `amount`, from -1 to 1.
+pub fn lighten(color: Rgba, amount: f64) -> Rgba {
+    let (hue, saturation, lightness) = to_hsl(color);
+    from_hsl(hue, saturation, lightness + amount, color[3])
+}
+
+/// Blends from `a` to `b`, where a `weight` of 0 is `a` and 1 is `b`.
+pub fn mix(a: Rgba, b: Rgba, weight: f64) -> Rgba {
+    let weight = weight.clamp(0.0, 1.0);
+    std::array::from_fn(|i| {
+        (f64::from(a[i]) + (f64::from(b[i]) - f64::from(a[i])) * weight).round() as u8
+    })
+}
+
+/// The color at `value` along a gradient through `stops`, which must be sorted by position.
+///
+/// Values before the first stop or after the last take that stop's color.
+pub fn gradient_at(value: i64, stops: &[(i64, Rgba)]) -> Option<Rgba> {
+    let after = stops.iter().position(|(position, _)| value < *position);
+    Some(match after {
+        Some(0) => stops[0].1,
+        Some(after) => {
+            let (start, from) = stops[after - 1];
+            let (end, to) = stops[after];
+            mix(from, to, (value - start) as f64 / (end - start) as f64)
+        }
+        None => stops.last()?.1,
+    })
+}
@@ -0,0 +1,551 @@
+//! Asynchronous subprocesses for `psybeam.command` and `psybeam.widget.refresh.stream`.
+//!
+//! Commands never block drawing: a widget is given the output of the command's previous run
+//! while a new one is in progress, and is redrawn once the new output is available.
+//! Streams run for as long as psybeam does, redrawing their widget whenever they print a line.
+//! Detached commands belong to no widget, and their output is only logged if they fail.
+
+use crate::{WidgetState, event, render};
+use rustix::fd::{AsFd, OwnedFd};
+use rustix::process::{Pid, PidfdFlags, Signal};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use std::os::unix::process::{CommandExt, ExitStatusExt};
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, Stdio};
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+/// Everything needed to run a command.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Spec {
+    pub argv: Box<[Rc<str>]>,
+    /// Written to the command's standard input, which is otherwise empty.
+    pub stdin: Option<Rc<str>>,
+    pub env: Box<[(Rc<str>, Rc<str>)]>,
+    /// Don't inherit psybeam's environment variables.
+    pub clear_env: bool,
+    pub cwd: Option<Rc<str>>,
+    pub timeout: Duration,
+}
+
+#[derive(Clone, Debug)]
+pub enum Status {
+    Exited(i32),
+    Signaled(i32),
+    /// Killed after running for longer than its timeout.
+    TimedOut,
+    /// The command couldn't be started (or waited on).
+    Failed(Rc<str>),
+}
+
+#[derive(Clone, Debug)]
+pub struct Output {
+    pub status: Status,
+    pub stdout: Rc<str>,
+    pub stderr: Rc<str>,
+}
+
+impl Output {
+    fn failed(error: io::Error) -> Self {
+        Self {
+            status: Status::Failed(error.to_string().into()),
+            stdout: Rc::from(""),
+            stderr: Rc::from(""),
+        }
+    }
+}
+
+struct Pipe<T> {
+    /// `None` once the child has closed its end.
+    reader: Option<T>,
+    buffer: Vec<u8>,
+}
+
+impl<T: Read + AsFd> Pipe<T> {
+    fn new(reader: Option<T>) -> io::Result<Self> {
+        if let Some(reader) = &reader {
+            rustix::io::ioctl_fionbio(reader, true)?;
+            event::register(reader)?;
+        }
+        Ok(Self {
+            reader,
+            buffer: Vec::new(),
+        })
+    }
+
+    /// Reads until the pipe is empty or closed.
+    fn drain(&mut self) {
+        let Some(reader) = &mut self.reader else {
+            return;
+        };
+        let mut chunk = [0; 4096];
+        loop {
+            match reader.read(&mut chunk) {
+                Ok(0) => {
+                    self.reader = None;
+                    return;
+                }
+                Ok(length) => self.buffer.extend_from_slice(&chunk[..length]),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    eprintln!("failed to read command output: {e}");
+                    self.reader = None;
+                    return;
+                }
+            }
+        }
+    }
+}
+
+struct Input {
+    /// `None` once all of the input has been written, closing the pipe.
+    writer: Option<ChildStdin>,
+    input: Rc<str>,
+    written: usize,
+}
+
+impl Input {
+    fn new(writer: Option<ChildStdin>, input: Rc<str>) -> io::Result<Self> {
+        if let Some(writer) = &writer {
+            rustix::io::ioctl_fionbio(writer, true)?;
+            event::register_writable(writer)?;
+        }
+        Ok(Self {
+            writer,
+            input,
+            written: 0,
+        })
+    }
+
+    /// Writes until the pipe is full or all of the input has been written.
+    fn fill(&mut self) {
+        let Some(writer) = &mut self.writer else {
+            return;
+        };
+        while self.written < self.input.len() {
+            match writer.write(&self.input.as_bytes()[self.written..]) {
+                Ok(length) => self.written += length,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => {}
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return,
+                // The child closed its standard input early, which is its prerogative.
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => break,
+                Err(e) => {
+                    eprintln!("failed to write command input: {e}");
+                    break;
+                }
+            }
+        }
+        self.writer = None;
+    }
+}
+
+struct Job {
+    child: Child,
+    /// Becomes readable when the child exits.
+    pidfd: OwnedFd,
+    stdin: Option<Input>,
+    stdout: Pipe<ChildStdout>,
+    stderr: Pipe<ChildStderr>,
+    deadline: Instant,
+    timed_out: bool,
+}
+
+fn command(argv: &[Rc<str>]) -> std::process::Command {
+    let mut command = std::process::Command::new(&*argv[0]);
+    command.args(argv[1..].iter().map(|argument| &**argument));
+    // SAFETY: prctl is async-signal-safe and doesn't allocate.
+    unsafe {
+        command.pre_exec(|| {
+            // Don't leave children behind if psybeam is killed.
+            rustix::process::set_parent_process_death_signal(Some(Signal::KILL))?;
+            Ok(())
+        });
+    }
+    command
+}
+
+/// Opens a descriptor which becomes readable when `child` exits.
+fn pidfd(child: &Child) -> io::Result<OwnedFd> {
+    let pidfd = rustix::process::pidfd_open(Pid::from_child(child), PidfdFlags::NONBLOCK)?;
+    event::register(&pidfd)?;
+    Ok(pidfd)
+}
+
+fn kill(child: &mut Child) {
+    if child.try_wait().is_ok_and(|status| status.is_none()) {
+        let _ = child.kill();
+        let _ = child.wait();
+    }
+}
+
+/// Finishes setting up a newly spawned child,
+/// killing it if that fails so that it isn't left running and then as a zombie.
+fn set_up<T>(
+    mut child: Child,
+    f: impl FnOnce(&mut Child) -> io::Result<T>,
+) -> io::Result<(Child, T)> {
+    match f(&mut child) {
+        Ok(value) => Ok((child, value)),
+        Err(e) => {
+            kill(&mut child);
+            Err(e)
+        }
+    }
+}
+
+impl Job {
+    fn spawn(spec: &Spec) -> io::Result<Self> {
+        let mut command = command(&spec.argv);
+        command
+            .stdin(if spec.stdin.is_some() {
+                Stdio::piped()
+            } else {
+                Stdio::null()
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+        if spec.clear_env {
+            command.env_clear();
+        }
+        command.envs(spec.env.iter().map(|(key, value)| (&**key, &**value)));
+        if let Some(cwd) = &spec.cwd {
+            command.current_dir(&**cwd);
+        }
+        let (child, (pidfd, stdin, stdout, stderr)) = set_up(command.spawn()?, |child| {
+            Ok((
+                pidfd(child)?,
+                spec.stdin
+                    .clone()
+                    .map(|stdin| Input::new(child.stdin.take(), stdin))
+                    .transpose()?,
+                Pipe::new(child.stdout.take())?,
+                Pipe::new(child.stderr.take())?,
+            ))
+        })?;
+        Ok(Self {
+            stdin,
+            stdout,
+            stderr,
+            child,
+            pidfd,
+            deadline: Instant::now() + spec.timeout,
+            timed_out: false,
+        })
+    }
+
+    /// Collects any available output, returning the final output once the child has exited.
+    fn poll(&mut self, now: Instant) -> Option<Output> {
+        if !self.timed_out && now >= self.deadline {
+            // If this fails, the child has already exited.
+            let _ = rustix::process::pidfd_send_signal(&self.pidfd, Signal::KILL);
+            self.timed_out = true;
+        }
+        if let Some(stdin) = &mut self.stdin {
+            stdin.fill();
+        }
+        // Grandchildren may hold the pipes open after the child exits,
+        // so the output is considered complete as soon as the child is reaped.
+        let status = self.child.try_wait();
+        self.stdout.drain();
+        self.stderr.drain();
+        let status = match status {
+            Ok(Some(status)) => status,
+            Ok(None) => return None,
+            Err(e) => return Some(Output::failed(e)),
+        };
+        Some(Output {
+            status: if self.timed_out {
+                Status::TimedOut
+            } else if let Some(signal) = status.signal() {
+                Status::Signaled(signal)
+            } else {
+                Status::Exited(status.code().unwrap_or_default())
+            },
+            stdout: String::from_utf8_lossy(&self.stdout.buffer).into(),
+            stderr: String::from_utf8_lossy(&self.stderr.buffer).into(),
+        })
+    }
+}
+
+impl Drop for Job {
+    fn drop(&mut self) {
+        kill(&mut self.child);
+    }
+}
+
+/// How long to wait before restarting a stream that exited,
+/// doubling each time it exits shortly after starting.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+struct StreamProcess {
+    child: Child,
+    _pidfd: OwnedFd,
+    stdout: Pipe<ChildStdout>,
+    started: Instant,
+}
+
+impl Drop for StreamProcess {
+    fn drop(&mut self) {
+        kill(&mut self.child);
+    }
+}
+
+struct Stream {
+    owner: Rc<WidgetState>,
+    argv: Box<[Rc<str>]>,
+    process: Option<StreamProcess>,
+    /// When to start the process again, if it isn't running.
+    restart: Instant,
+    backoff: Duration,
+}
+
+impl Stream {
+    fn start(&mut self, now: Instant) {
+        let process = command(&self.argv)
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            // Diagnostics are left for psybeam's own stderr.
+            .stderr(Stdio::inherit())
+            .spawn()
+            .and_then(|child| {
+                set_up(child, |child| {
+                    Ok((pidfd(child)?, Pipe::new(child.stdout.take())?))
+                })
+            })
+            .map(|(child, (pidfd, stdout))| StreamProcess {
+                _pidfd: pidfd,
+                stdout,
+                child,
+                started: now,
+            });
+        match process {
+            Ok(process) => self.process = Some(process),
+            Err(e) => {
+                eprintln!("failed to start stream {}: {e}", self.argv[0]);
+                self.stopped(now);
+            }
+        }
+    }
+
+    fn stopped(&mut self, now: Instant) {
+        self.restart = now + self.backoff;
+        self.backoff = (self.backoff * 2).min(MAX_BACKOFF);
+    }
+
+    fn update(&mut self, now: Instant) {
+        let Some(process) = &mut self.process else {
+            if now >= self.restart {
+                self.start(now);
+            }
+            return;
+        };
+        let status = process.child.try_wait();
+        process.stdout.drain();
+        let buffer = &mut process.stdout.buffer;
+        let exited = !matches!(status, Ok(None));
+        // Only complete lines are shown, unless there won't be any more output.
+        let end = if exited {
+            buffer.len()
+        } else {
+            buffer
+                .iter()
+                .rposition(|&byte| byte == b'\n')
+                .map_or(0, |newline| newline + 1)
+        };
+        if end > 0 {
+            let lines = buffer.drain(..end).collect::<Vec<u8>>();
+            if let Some(line) = lines
+                .split(|&byte| byte == b'\n')
+                .rfind(|line| !line.is_empty())
+            {
+                *self.owner.line.borrow_mut() = Some(String::from_utf8_lossy(line).into());
+                self.owner.dirty.set(true);
+            }
+        }
+        if exited {
+            match status {
+                Ok(Some(status)) => eprintln!("stream {} exited ({status})", self.argv[0]),
+                Err(e) => eprintln!("failed to wait for stream {}: {e}", self.argv[0]),
+                Ok(None) => {}
+            }
+            // A stream that ran for a while isn't failing in a loop.
+            if now.duration_since(process.started) >= MAX_BACKOFF {
+                self.backoff = MIN_BACKOFF;
+            }
+            self.process = None;
+            self.stopped(now);
+        }
+    }
+}
+
+#[derive(Default)]
+struct Entry {
+    /// The widget to redraw when the command finishes.
+    owner: Option<Rc<WidgetState>>,
+    job: Option<Job>,
+    last: Option<Output>,
+    /// Whether `last` hasn't been returned to the widget yet.
+    fresh: bool,
+    /// Whether the owner has run the command during its current draw.
+    requested: bool,
+}
+
+/// The address of the widget that ran the command, and the command itself.
+///
+/// Widgets sharing a command are given separate entries
+/// so that they don't consume each other's output.
+type Key = (usize, Spec);
+
+#[derive(Default)]
+struct Commands {
+    entries: HashMap<Key, Entry>,
+    /// Jobs started by [`detach`], with the program each is running.
+    detached: Vec<(Rc<str>, Job)>,
+}
+
+thread_local! {
+    static COMMANDS: RefCell<Commands> = RefCell::default();
+    static STREAMS: RefCell<Vec<Stream>> = RefCell::default();
+}
+
+/// Forgets the commands a widget ran before, but not during the draw it just finished,
+/// killing any still running,
+/// so that commands whose arguments change on every draw don't accumulate.
+pub fn forget_unused(owner: &Rc<WidgetState>) {
+    let id = Rc::as_ptr(owner) as usize;
+    COMMANDS.with_borrow_mut(|commands| {
+        commands
+            .entries
+            .retain(|(owner, _), entry| *owner != id || std::mem::take(&mut entry.requested));
+    });
+}
+
+/// Returns the most recent output of a command, starting a new run of it if the last one
+/// has already been seen.
+///
+/// Commands are attributed to the widget being drawn, which is redrawn when they finish.
+///
+/// The boolean is true if the command is still running,
+/// in which case the output (if any) is from a previous run.
+pub fn run(spec: Spec) -> (Option<Output>, bool) {
+    COMMANDS.with_borrow_mut(|commands| {
+        let owner = render::drawing().map(|widget| widget.state);
+        let id = owner.as_ref().map_or(0, |owner| Rc::as_ptr(owner) as usize);
+        let entry = commands.entries.entry((id, spec.clone())).or_default();
+        entry.owner = owner;
+        entry.requested = true;
+        if entry.job.is_some() {
+            return (entry.last.clone(), true);
+        }
+        if entry.fresh {
+            entry.fresh = false;
+            return (entry.last.clone(), false);
+        }
+        match Job::spawn(&spec) {
+            Ok(job) => {
+                entry.job = Some(job);
+                (entry.last.clone(), true)
+            }
+            Err(e) => (Some(Output::failed(e)), false),
+        }
+    })
+}
+
+/// Starts a command in the background that isn't cached or tied to any widget,
+/// logging it if the command fails.
+///
+/// Only failing to start the command is reported to the caller.
+pub fn detach(spec: Spec) -> io::Result<()> {
+    let job = Job::spawn(&spec)?;
+    COMMANDS.with_borrow_mut(|commands| commands.detached.push((spec.argv[0].clone(), job)));
+    Ok(())
+}
+
+/// Starts a command whose lines of output are given to `owner`, restarting it whenever it exits.
+pub fn stream(owner: &Rc<WidgetState>, argv: Box<[Rc<str>]>) {
+    let now = Instant::now();
+    let mut stream = Stream {
+        owner: owner.clone(),
+        argv,
+        process: None,
+        restart: now,
+        backoff: MIN_BACKOFF,
+    };
+    stream.start(now);
+    STREAMS.with_borrow_mut(|streams| streams.push(stream));
+}
+
+/// Collects output from running commands, and marks their widgets for redrawing once they finish.
+///
+/// Streams are also read from, and restarted if they've been stopped for long enough.
+pub fn update() {
+    let now = Instant::now();
+    STREAMS.with_borrow_mut(|streams| {
+        for stream in streams {
+            stream.update(now);
+        }
+    });
+    COMMANDS.with_borrow_mut(|commands| {
+        for entry in commands.entries.values_mut() {
+            let Some(job) = &mut entry.job else {
+                continue;
+            };
+            if let Some(output) = job.poll(now) {
+                entry.job = None;
+                entry.last = Some(output);
+                entry.fresh = true;
+                if let Some(owner) = &entry.owner {
+                    owner.output_ready.set(true);
+                }
+            }
+        }
+        commands.detached.retain_mut(|(program, job)| {
+            let Some(output) = job.poll(now) else {
+                return true;
+            };
+            let failure = match output.status {
+                Status::Exited(0) => return false,
+                Status::Exited(status) => format!("exited with status {status}"),
+                Status::Signaled(signal) => format!("was killed by signal {signal}"),
+                Status::TimedOut => "timed out".into(),
+                Status::Failed(error) => error.to_string(),
+            };
+            match output.stderr.trim() {
+                "" => eprintln!("{program} {failure}"),
+                stderr => eprintln!("{program} {failure}: {stderr}"),
+            }
+            false
+        });
+    });
+}
+
+/// The next time a running command will need to be killed, or a stream restarted.
+pub fn next_deadline() -> Option<Instant> {
+    let commands = COMMANDS.with_borrow(|commands| {
+        commands
+            .entries
+            .values()
+            .filter_map(|entry| entry.job.as_ref())
+            .chain(commands.detached.iter().map(|(_, job)| job))
+            .filter(|job| !job.timed_out)
+            .map(|job| job.deadline)
+            .min()
+    });
+    let streams = STREAMS.with_borrow(|streams| {
+        streams
+            .iter()
+            .filter(|stream| stream.process.is_none())
+            .map(|stream| stream.restart)
+            .min()
+    });
+    commands.into_iter().chain(streams).min()
+}
+
+/// Kills every running command and stream.
+pub fn clear() {
+    COMMANDS.take();
+    STREAMS.take();
+}
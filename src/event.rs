@@ -0,0 +1,53 @@
+//! Waiting on everything psybeam reacts to besides the Wayland connection.
+//!
+//! Event sources register their file descriptors with a single epoll instance,
+//! so the main loop only ever has to poll two descriptors.
+//! Sources are level-triggered: they must drain (or close) their descriptors when updated,
+//! and closing a descriptor is enough to unregister it.
+
+use rustix::event::{PollFd, PollFlags, Timespec, epoll};
+use rustix::fd::{AsFd, OwnedFd};
+use rustix::io::Errno;
+use std::io;
+use std::time::Instant;
+
+thread_local! {
+    static EPOLL: OwnedFd =
+        epoll::create(epoll::CreateFlags::CLOEXEC).expect("failed to create epoll instance");
+}
+
+fn add(fd: impl AsFd, flags: epoll::EventFlags) -> io::Result<()> {
+    EPOLL.with(|epoll| epoll::add(epoll, fd, epoll::EventData::new_u64(0), flags))?;
+    Ok(())
+}
+
+/// Wakes the main loop whenever `fd` becomes readable.
+pub fn register(fd: impl AsFd) -> io::Result<()> {
+    add(fd, epoll::EventFlags::IN)
+}
+
+/// Wakes the main loop whenever `fd` becomes writable.
+pub fn register_writable(fd: impl AsFd) -> io::Result<()> {
+    add(fd, epoll::EventFlags::OUT)
+}
+
+/// Blocks until the Wayland connection or a registered source is readable,
+/// or until `deadline` passes.
+///
+/// Returns whether the Wayland connection is readable.
+pub fn wait(wayland: impl AsFd, deadline: Option<Instant>) -> io::Result<bool> {
+    let timeout = deadline.and_then(|deadline| {
+        Timespec::try_from(deadline.saturating_duration_since(Instant::now())).ok()
+    });
+    EPOLL.with(|epoll| {
+        let mut fds = [
+            PollFd::new(&wayland, PollFlags::IN),
+            PollFd::new(epoll, PollFlags::IN),
+        ];
+        match rustix::event::poll(&mut fds, timeout.as_ref()) {
+            Ok(_) => Ok(fds[0].revents().contains(PollFlags::IN)),
+            Err(Errno::INTR) => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    })
+}
@@ -0,0 +1,102 @@
+//! Mounted filesystems and their usage, for `psybeam.fs`.
+
+use crate::sys;
+use std::rc::Rc;
+use std::{fs, io};
+
+/// The capacity of a filesystem, in bytes and inodes.
+#[derive(Debug)]
+pub struct Usage {
+    pub total: i64,
+    /// Free space, including the space reserved for root.
+    pub free: i64,
+    /// Free space available to unprivileged users.
+    pub available: i64,
+    pub inodes: i64,
+    pub inodes_free: i64,
+}
+
+impl Usage {
+    pub fn used(&self) -> i64 {
+        self.total - self.free
+    }
+}
+
+/// Usage of the filesystem containing `path`.
+pub fn usage(path: &str) -> io::Result<Usage> {
+    let stat = rustix::fs::statvfs(path)?;
+    let count = |count: u64| i64::try_from(count).unwrap_or(i64::MAX);
+    let bytes = |blocks: u64| count(blocks.saturating_mul(stat.f_frsize));
+    Ok(Usage {
+        total: bytes(stat.f_blocks),
+        free: bytes(stat.f_bfree),
+        available: bytes(stat.f_bavail),
+        inodes: count(stat.f_files),
+        inodes_free: count(stat.f_ffree),
+    })
+}
+
+#[derive(Debug)]
+pub struct Mount {
+    /// The device or other source, such as "/dev/nvme0n1p2" or "tmpfs".
+    pub source: Rc<str>,
+    pub path: Rc<str>,
+    /// The filesystem type, such as "ext4".
+    pub kind: Rc<str>,
+    /// The per-mount options, such as "rw,relatime".
+    pub options: Rc<str>,
+}
+
+impl Mount {
+    pub fn read_only(&self) -> bool {
+        self.options.split(',').any(|option| option == "ro")
+    }
+}
+
+/// Decodes the octal escapes used for spaces and other whitespace in /proc/self/mountinfo.
+fn unescape(field: &str) -> Rc<str> {
+    if !field.contains('\\') {
+        return field.into();
+    }
+    let mut bytes = Vec::with_capacity(field.len());
+    let mut rest = field.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail
+            .get(..3)
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u8::from_str_radix(digits, 8).ok());
+        match escaped {
+            Some(escaped) if byte == b'\\' => {
+                bytes.push(escaped);
+                rest = &tail[3..];
+            }
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into()
+}
+
+/// Every mount visible to psybeam, in the order they were mounted.
+pub fn mounts() -> io::Result<Vec<Mount>> {
+    let mountinfo = fs::read_to_string(sys::procfs("self/mountinfo"))?;
+    Ok(mountinfo
+        .lines()
+        .filter_map(|line| {
+            // The optional fields before the separator vary in number.
+            let (mount, filesystem) = line.split_once(" - ")?;
+            let mut mount = mount.split(' ');
+            let path = mount.nth(4)?;
+            let options = mount.next()?;
+            let mut filesystem = filesystem.split(' ');
+            Some(Mount {
+                kind: unescape(filesystem.next()?),
+                source: unescape(filesystem.next()?),
+                path: unescape(path),
+                options: options.into(),
+            })
+        })
+        .collect())
+}
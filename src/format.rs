@@ -0,0 +1,207 @@
+//! Formatting numbers for display, for `psybeam.format`.
+//!
+//! Widgets are redrawn in place, so these aim for output whose width doesn't jump around
+//! as values change: a fixed number of decimals, and padding to a given width.
+
+use std::fmt;
+use std::rc::Rc;
+
+/// Formats a size such as "1.2 MiB", using powers of 1024, or powers of 1000 if `si` is set.
+pub fn bytes(bytes: i64, si: bool) -> String {
+    let (base, units) = if si {
+        (1000.0, ["B", "kB", "MB", "GB", "TB", "PB", "EB"])
+    } else {
+        (1024.0, ["B", "KiB", "MiB", "GiB", "TiB", "PiB", "EiB"])
+    };
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    // Moves up a unit before rounding would produce "1024.0 KiB".
+    while value.abs() >= base - 0.05 && unit < units.len() - 1 {
+        value /= base;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{bytes} B")
+    } else {
+        format!("{value:.1} {}", units[unit])
+    }
+}
+
+/// Formats a number of seconds as its two most significant units, such as "2h 13m".
+pub fn duration(seconds: i64) -> String {
+    const UNITS: [(&str, u64); 4] = [("d", 86400), ("h", 3600), ("m", 60), ("s", 1)];
+    let sign = if seconds < 0 { "-" } else { "" };
+    let seconds = seconds.unsigned_abs();
+    let largest = UNITS
+        .iter()
+        .position(|(_, size)| seconds >= *size)
+        .unwrap_or(UNITS.len() - 1);
+    let (unit, size) = UNITS[largest];
+    match UNITS.get(largest + 1) {
+        Some((next, next_size)) => format!(
+            "{sign}{}{unit} {}{next}",
+            seconds / size,
+            seconds % size / next_size
+        ),
+        None => format!("{sign}{seconds}{unit}"),
+    }
+}
+
+/// `part` as a rounded percentage of `total`, such as "42%".
+pub fn percent(part: i64, total: i64) -> String {
+    let percent = if total == 0 {
+        0
+    } else {
+        (part as f64 * 100.0 / total as f64).round() as i64
+    };
+    format!("{percent}%")
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+/// Pads `text` with `fill` up to `width` characters.
+pub fn pad(text: &str, width: usize, align: Align, fill: char) -> String {
+    let padding = width.saturating_sub(text.chars().count());
+    let (before, after) = match align {
+        Align::Left => (0, padding),
+        Align::Center => (padding / 2, padding - padding / 2),
+        Align::Right => (padding, 0),
+    };
+    let mut padded = String::with_capacity(text.len() + padding);
+    padded.extend(std::iter::repeat_n(fill, before));
+    padded.push_str(text);
+    padded.extend(std::iter::repeat_n(fill, after));
+    padded
+}
+
+pub enum Argument {
+    Integer(i64),
+    String(Rc<str>),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "invalid format string at byte {}: {}",
+            self.offset, self.message
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Fills in a template such as "{:>3}%", in the manner of Rust's `format!`.
+///
+/// Placeholders are `{}` or `{index}`, optionally followed by `:` and a spec made of
+/// a fill and alignment (`<`, `^`, or `>`), a `0` to zero-pad integers, and a width,
+/// each of which may be left out.
+/// Integers are aligned right by default, and strings left.
+pub fn template(template: &str, arguments: &[Argument]) -> Result<String, Error> {
+    let error = |offset: usize, message: &str| Error {
+        offset,
+        message: message.into(),
+    };
+    let mut output = String::with_capacity(template.len());
+    let mut next_argument = 0;
+    let mut characters = template.char_indices().peekable();
+    while let Some((offset, character)) = characters.next() {
+        match character {
+            '{' if characters.next_if(|(_, c)| *c == '{').is_some() => output.push('{'),
+            '}' if characters.next_if(|(_, c)| *c == '}').is_some() => output.push('}'),
+            '}' => return Err(error(offset, "unmatched '}'")),
+            '{' => {
+                let start = offset + 1;
+                let Some(end) = template[start..].find('}').map(|end| start + end) else {
+                    return Err(error(offset, "unterminated placeholder"));
+                };
+                while characters.next_if(|(i, _)| *i <= end).is_some() {}
+                let (index, spec) = template[start..end]
+                    .split_once(':')
+                    .unwrap_or((&template[start..end], ""));
+                let index = if index.is_empty() {
+                    next_argument += 1;
+                    next_argument - 1
+                } else {
+                    index
+                        .parse()
+                        .map_err(|_| error(start, "expected an argument index"))?
+                };
+                let argument = arguments
+                    .get(index)
+                    .ok_or_else(|| error(start, &format!("no argument {index}")))?;
+                write_argument(&mut output, argument, spec)
+                    .map_err(|message| error(start, message))?;
+            }
+            _ => output.push(character),
+        }
+    }
+    Ok(output)
+}
+
+fn write_argument(
+    output: &mut String,
+    argument: &Argument,
+    spec: &str,
+) -> Result<(), &'static str> {
+    let alignment = |c: char| match c {
+        '<' => Some(Align::Left),
+        '^' => Some(Align::Center),
+        '>' => Some(Align::Right),
+        _ => None,
+    };
+    let mut characters = spec.chars();
+    let (fill, align, rest) = match (characters.next(), characters.next()) {
+        (Some(fill), Some(c)) if alignment(c).is_some() => {
+            (fill, alignment(c), characters.as_str())
+        }
+        (Some(c), _) if alignment(c).is_some() => (' ', alignment(c), &spec[1..]),
+        _ => (' ', None, spec),
+    };
+    let (zero, width) = match rest.strip_prefix('0') {
+        Some(width) if !width.is_empty() => (true, width),
+        _ => (false, rest),
+    };
+    let width: usize = if width.is_empty() {
+        0
+    } else {
+        width.parse().map_err(|_| "expected a width")?
+    };
+    match argument {
+        Argument::Integer(integer) if zero => {
+            // Zeros go between the sign and the digits, and count towards the width.
+            let sign = if *integer < 0 { "-" } else { "" };
+            let digits = integer.unsigned_abs().to_string();
+            output.push_str(sign);
+            output.push_str(&pad(
+                &digits,
+                width.saturating_sub(sign.len()),
+                Align::Right,
+                '0',
+            ));
+        }
+        Argument::Integer(integer) => {
+            output.push_str(&pad(
+                &integer.to_string(),
+                width,
+                align.unwrap_or(Align::Right),
+                fill,
+            ));
+        }
+        Argument::String(string) => {
+            output.push_str(&pad(string, width, align.unwrap_or(Align::Left), fill));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,110 @@
+//! The machine and user psybeam is running for, for `psybeam.env`.
+
+use std::ffi::CStr;
+use std::rc::Rc;
+use std::{env, fs, io};
+
+/// Arguments given after the script's path.
+pub fn args() -> Vec<Rc<str>> {
+    env::args_os()
+        .skip(2)
+        .map(|argument| argument.to_string_lossy().into())
+        .collect()
+}
+
+#[derive(Debug)]
+pub struct Uname {
+    /// The kernel's name, such as "Linux".
+    pub sysname: Rc<str>,
+    pub nodename: Rc<str>,
+    /// The kernel's release, such as "6.17.1-arch1-1".
+    pub release: Rc<str>,
+    pub version: Rc<str>,
+    /// The hardware architecture, such as "x86_64".
+    pub machine: Rc<str>,
+}
+
+pub fn uname() -> Uname {
+    let uname = rustix::system::uname();
+    let string = |field: &CStr| Rc::from(field.to_string_lossy());
+    Uname {
+        sysname: string(uname.sysname()),
+        nodename: string(uname.nodename()),
+        release: string(uname.release()),
+        version: string(uname.version()),
+        machine: string(uname.machine()),
+    }
+}
+
+pub fn hostname() -> Rc<str> {
+    uname().nodename
+}
+
+/// The name of the effective user, from the password database or else `$USER`.
+pub fn username() -> io::Result<Rc<str>> {
+    let mut buffer = vec![0; 1024];
+    loop {
+        let mut passwd = std::mem::MaybeUninit::<libc::passwd>::uninit();
+        let mut result = std::ptr::null_mut();
+        // SAFETY: The buffer and its length match,
+        // and the strings written to it are only read while it's alive.
+        let error = unsafe {
+            libc::getpwuid_r(
+                libc::geteuid(),
+                passwd.as_mut_ptr(),
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        };
+        if error == libc::ERANGE {
+            buffer.resize(buffer.len() * 2, 0);
+            continue;
+        }
+        if error != 0 {
+            return Err(io::Error::from_raw_os_error(error));
+        }
+        if result.is_null() {
+            // Users without an entry, such as those in some containers.
+            return env::var("USER").map(Rc::from).map_err(|_| {
+                io::Error::new(io::ErrorKind::NotFound, "the current user has no name")
+            });
+        }
+        // SAFETY: getpwuid_r succeeded, so the entry is initialized and points into the buffer.
+        let name = unsafe { CStr::from_ptr((*result).pw_name) };
+        return Ok(name.to_string_lossy().into());
+    }
+}
+
+/// Reads a value from os-release, which is quoted like a shell variable.
+fn unquote(value: &str) -> String {
+    let mut unquoted = String::with_capacity(value.len());
+    let mut characters = value.chars();
+    let mut quote = None;
+    while let Some(character) = characters.next() {
+        match (quote, character) {
+            (None, '"' | '\'') => quote = Some(character),
+            (Some(open), _) if character == open => quote = None,
+            (Some('\''), _) => unquoted.push(character),
+            (_, '\\') => unquoted.extend(characters.next()),
+            _ => unquoted.push(character),
+        }
+    }
+    unquoted
+}
+
+/// The fields of /etc/os-release, such as ("ID", "arch"), in the order they're listed.
+pub fn os_release() -> io::Result<Vec<(Rc<str>, Rc<str>)>> {
+    // /usr/lib/os-release is the fallback for systems which don't link it from /etc.
+    let os_release = fs::read_to_string("/etc/os-release")
+        .or_else(|_| fs::read_to_string("/usr/lib/os-release"))?;
+    Ok(os_release
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let (key, value) = line.split_once('=')?;
+            Some((key.into(), unquote(value).into()))
+        })
+        .collect())
+}
@@ -0,0 +1,251 @@
+//! Icon name resolution, following the freedesktop icon theme specification:
+//! <https://specifications.freedesktop.org/icon-theme-spec/latest/>
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+/// Only formats that [`crate::image`] can decode are considered.
+const EXTENSIONS: [&str; 2] = ["png", "svg"];
+
+#[derive(Debug)]
+enum DirectoryType {
+    Fixed,
+    Scalable,
+    Threshold,
+}
+
+#[derive(Debug)]
+struct Directory {
+    path: String,
+    size: i64,
+    scale: i64,
+    kind: DirectoryType,
+    min_size: i64,
+    max_size: i64,
+    threshold: i64,
+}
+
+impl Directory {
+    fn matches_size(&self, size: i64, scale: i64) -> bool {
+        if self.scale != scale {
+            return false;
+        }
+        match self.kind {
+            DirectoryType::Fixed => self.size == size,
+            DirectoryType::Scalable => (self.min_size..=self.max_size).contains(&size),
+            DirectoryType::Threshold => {
+                (self.size - self.threshold..=self.size + self.threshold).contains(&size)
+            }
+        }
+    }
+
+    fn size_distance(&self, size: i64, scale: i64) -> i64 {
+        let scaled = size * scale;
+        let (min, max) = match self.kind {
+            DirectoryType::Fixed => (self.size, self.size),
+            DirectoryType::Scalable => (self.min_size, self.max_size),
+            DirectoryType::Threshold => (self.size - self.threshold, self.size + self.threshold),
+        };
+        if scaled < min * self.scale {
+            self.min_size * self.scale - scaled
+        } else if scaled > max * self.scale {
+            scaled - self.max_size * self.scale
+        } else {
+            0
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Theme {
+    /// Every base directory that contains this theme.
+    roots: Vec<PathBuf>,
+    inherits: Vec<String>,
+    directories: Vec<Directory>,
+}
+
+impl Theme {
+    fn load(name: &str, base_dirs: &[PathBuf]) -> Option<Self> {
+        let roots = base_dirs
+            .iter()
+            .map(|base| base.join(name))
+            .filter(|root| root.is_dir())
+            .collect::<Vec<_>>();
+        let index = roots
+            .iter()
+            .find_map(|root| fs::read_to_string(root.join("index.theme")).ok())?;
+        let sections = parse_ini(&index);
+        let theme = sections.get("Icon Theme")?;
+        let list = |key| {
+            theme
+                .get(key)
+                .into_iter()
+                .flat_map(|value: &&str| value.split(','))
+                .map(str::trim)
+                .filter(|entry| !entry.is_empty())
+        };
+        let inherits = list("Inherits").map(String::from).collect();
+        let directories = list("Directories")
+            .chain(list("ScaledDirectories"))
+            .filter_map(|path| {
+                let section = sections.get(path)?;
+                let get = |key, default| {
+                    section
+                        .get(key)
+                        .and_then(|value| value.parse().ok())
+                        .unwrap_or(default)
+                };
+                let size = section.get("Size")?.parse().ok()?;
+                Some(Directory {
+                    path: path.into(),
+                    size,
+                    scale: get("Scale", 1),
+                    kind: match section.get("Type").copied() {
+                        Some("Fixed") => DirectoryType::Fixed,
+                        Some("Scalable") => DirectoryType::Scalable,
+                        _ => DirectoryType::Threshold,
+                    },
+                    min_size: get("MinSize", size),
+                    max_size: get("MaxSize", size),
+                    threshold: get("Threshold", 2),
+                })
+            })
+            .collect();
+        Some(Self {
+            roots,
+            inherits,
+            directories,
+        })
+    }
+
+    fn candidates<'a>(
+        &'a self,
+        name: &'a str,
+    ) -> impl Iterator<Item = (&'a Directory, PathBuf)> + 'a {
+        self.directories.iter().flat_map(move |directory| {
+            self.roots.iter().flat_map(move |root| {
+                EXTENSIONS.iter().map(move |extension| {
+                    (
+                        directory,
+                        root.join(&directory.path)
+                            .join(format!("{name}.{extension}")),
+                    )
+                })
+            })
+        })
+    }
+
+    fn lookup(&self, name: &str, size: i64, scale: i64) -> Option<PathBuf> {
+        if let Some((_, path)) = self
+            .candidates(name)
+            .find(|(directory, path)| directory.matches_size(size, scale) && path.is_file())
+        {
+            return Some(path);
+        }
+        self.candidates(name)
+            .filter(|(_, path)| path.is_file())
+            .min_by_key(|(directory, _)| directory.size_distance(size, scale))
+            .map(|(_, path)| path)
+    }
+}
+
+/// Parses the desktop entry-style ini format used by `index.theme`.
+///
+/// Localized keys are kept verbatim (e.g. `Name[de]`) and are simply never queried.
+fn parse_ini(source: &str) -> HashMap<&str, HashMap<&str, &str>> {
+    let mut sections = HashMap::<&str, HashMap<&str, &str>>::new();
+    let mut current = None;
+    for line in source.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some(section) = line.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            current = Some(section);
+        } else if let (Some(section), Some((key, value))) = (current, line.split_once('=')) {
+            sections
+                .entry(section)
+                .or_default()
+                .insert(key.trim(), value.trim());
+        }
+    }
+    sections
+}
+
+fn base_dirs() -> Vec<PathBuf> {
+    let home = env::var_os("HOME").map(PathBuf::from);
+    let data_home = env::var_os("XDG_DATA_HOME")
+        .map(PathBuf::from)
+        .or_else(|| home.as_ref().map(|home| home.join(".local/share")));
+    let data_dirs = env::var("XDG_DATA_DIRS")
+        .ok()
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/usr/local/share:/usr/share".into());
+    home.map(|home| home.join(".icons"))
+        .into_iter()
+        .chain(data_home.map(|data_home| data_home.join("icons")))
+        .chain(data_dirs.split(':').map(|dir| Path::new(dir).join("icons")))
+        .chain([PathBuf::from("/usr/share/pixmaps")])
+        .collect()
+}
+
+/// Resolves icon names to files within a theme, its parents, and `hicolor`.
+pub struct IconThemes {
+    base_dirs: Vec<PathBuf>,
+    /// Themes in the order they are searched:
+    /// the configured theme, its parents depth-first, and finally hicolor.
+    themes: Vec<Theme>,
+    cache: HashMap<(Box<str>, u32), Option<PathBuf>>,
+}
+
+impl IconThemes {
+    pub fn new(theme: &str) -> Self {
+        let base_dirs = base_dirs();
+        let mut themes = Vec::new();
+        let mut visited = Vec::new();
+        let mut pending = vec![String::from(theme)];
+        while let Some(name) = pending.pop() {
+            if visited.contains(&name) {
+                continue;
+            }
+            if let Some(theme) = Theme::load(&name, &base_dirs) {
+                pending.extend(theme.inherits.iter().rev().cloned());
+                themes.push(theme);
+            } else {
+                eprintln!("icon theme {name} not found");
+            }
+            visited.push(name);
+        }
+        // hicolor is always searched, but only after everything else.
+        if !visited.iter().any(|name| name == "hicolor") {
+            themes.extend(Theme::load("hicolor", &base_dirs));
+        }
+        Self {
+            base_dirs,
+            themes,
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Returns the path of the icon closest to `size`×`size` pixels, if any.
+    pub fn find(&mut self, name: &str, size: u32) -> Option<&Path> {
+        // psybeam doesn't support output scaling yet.
+        const SCALE: i64 = 1;
+        self.cache
+            .entry((name.into(), size))
+            .or_insert_with(|| {
+                self.themes
+                    .iter()
+                    .find_map(|theme| theme.lookup(name, size.into(), SCALE))
+                    .or_else(|| {
+                        self.base_dirs.iter().find_map(|base| {
+                            EXTENSIONS
+                                .iter()
+                                .map(|extension| base.join(format!("{name}.{extension}")))
+                                .find(|path| path.is_file())
+                        })
+                    })
+            })
+            .as_deref()
+    }
+}
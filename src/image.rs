@@ -0,0 +1,77 @@
+use resvg::usvg;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use tiny_skia::{FilterQuality, Pixmap, PixmapPaint, Transform};
+
+struct CachedImage {
+    modified: SystemTime,
+    pixmap: Pixmap,
+}
+
+/// Decoded images, rasterized at the size they were requested at.
+///
+/// Entries are reloaded when the file's modification time changes,
+/// so icons can be swapped out without restarting psybeam.
+#[derive(Default)]
+pub struct ImageCache {
+    entries: HashMap<(PathBuf, u32), CachedImage>,
+}
+
+impl ImageCache {
+    /// Returns the image at `path`, scaled to fit within a `size`×`size` square.
+    pub fn get(&mut self, path: &Path, size: u32) -> anyhow::Result<&Pixmap> {
+        let modified = fs::metadata(path)?.modified()?;
+        let key = (path.to_path_buf(), size);
+        if self
+            .entries
+            .get(&key)
+            .is_none_or(|cached| cached.modified != modified)
+        {
+            let pixmap = load(path, size)?;
+            self.entries
+                .insert(key.clone(), CachedImage { modified, pixmap });
+        }
+        Ok(&self.entries[&key].pixmap)
+    }
+}
+
+/// Centers an image of the given dimensions within a `size`×`size` square.
+fn fit(width: f32, height: f32, size: u32) -> Transform {
+    let size = size as f32;
+    let scale = size / width.max(height);
+    Transform::from_scale(scale, scale)
+        .post_translate((size - width * scale) / 2.0, (size - height * scale) / 2.0)
+}
+
+fn load(path: &Path, size: u32) -> anyhow::Result<Pixmap> {
+    let data = fs::read(path)?;
+    let mut pixmap =
+        Pixmap::new(size, size).ok_or_else(|| anyhow::anyhow!("image size must be nonzero"))?;
+    // Anything that isn't a PNG is assumed to be an SVG;
+    // usvg's error message is clear enough if it isn't.
+    if data.starts_with(b"\x89PNG") {
+        let image = Pixmap::decode_png(&data)?;
+        pixmap.draw_pixmap(
+            0,
+            0,
+            image.as_ref(),
+            &PixmapPaint {
+                quality: FilterQuality::Bicubic,
+                ..Default::default()
+            },
+            fit(image.width() as f32, image.height() as f32, size),
+            None,
+        );
+    } else {
+        let tree = usvg::Tree::from_data(&data, &usvg::Options::default())?;
+        let tree_size = tree.size();
+        resvg::render(
+            &tree,
+            fit(tree_size.width(), tree_size.height(), size),
+            &mut pixmap.as_mut(),
+        );
+    }
+    Ok(pixmap)
+}
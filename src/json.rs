@@ -0,0 +1,437 @@
+//! A small JSON parser and encoder for `psybeam.json`.
+//!
+//! Errors carry the byte offset of the malformed input,
+//! which is more useful than a line number for the single-line output of most tools.
+
+use std::fmt::{self, Write};
+use std::rc::Rc;
+
+/// Nested arrays and objects deeper than this are rejected rather than overflowing the stack.
+const MAX_DEPTH: usize = 128;
+
+#[derive(Debug, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Integer(i64),
+    /// A number which doesn't fit in an i64, such as a fraction, kept as its source text.
+    Number(Rc<str>),
+    String(Rc<str>),
+    Array(Vec<Json>),
+    Object(Vec<(Rc<str>, Json)>),
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub offset: usize,
+    pub message: String,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid JSON at byte {}: {}", self.offset, self.message)
+    }
+}
+
+impl std::error::Error for Error {}
+
+struct Parser<'source> {
+    source: &'source [u8],
+    offset: usize,
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn error<T>(&self, message: impl Into<String>) -> Result<T, Error> {
+        Err(Error {
+            offset: self.offset,
+            message: message.into(),
+        })
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.source.get(self.offset).copied()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.peek() {
+            self.offset += 1;
+        }
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<(), Error> {
+        if self.peek() == Some(byte) {
+            self.offset += 1;
+            Ok(())
+        } else {
+            self.unexpected(&format!("'{}'", byte as char))
+        }
+    }
+
+    fn unexpected<T>(&self, expected: &str) -> Result<T, Error> {
+        match self.peek() {
+            Some(byte) if byte.is_ascii_graphic() => {
+                self.error(format!("expected {expected}, found '{}'", byte as char))
+            }
+            Some(byte) => self.error(format!("expected {expected}, found byte {byte:#04x}")),
+            None => self.error(format!("expected {expected}, found end of input")),
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, Error> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'"') => Ok(Json::String(self.string()?)),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'{') => self.nested(Self::object),
+            _ => self.unexpected("a value"),
+        }
+    }
+
+    fn literal(&mut self, literal: &str, value: Json) -> Result<Json, Error> {
+        if self.source[self.offset..].starts_with(literal.as_bytes()) {
+            self.offset += literal.len();
+            Ok(value)
+        } else {
+            self.error(format!("expected {literal}"))
+        }
+    }
+
+    fn nested(&mut self, f: fn(&mut Self) -> Result<Json, Error>) -> Result<Json, Error> {
+        if self.depth == MAX_DEPTH {
+            return self.error("nested too deeply");
+        }
+        self.depth += 1;
+        let value = f(self)?;
+        self.depth -= 1;
+        Ok(value)
+    }
+
+    fn digits(&mut self) -> Result<(), Error> {
+        if !matches!(self.peek(), Some(b'0'..=b'9')) {
+            return self.unexpected("a digit");
+        }
+        while let Some(b'0'..=b'9') = self.peek() {
+            self.offset += 1;
+        }
+        Ok(())
+    }
+
+    fn number(&mut self) -> Result<Json, Error> {
+        let start = self.offset;
+        if self.peek() == Some(b'-') {
+            self.offset += 1;
+        }
+        if self.peek() == Some(b'0') {
+            self.offset += 1;
+        } else {
+            self.digits()?;
+        }
+        if self.peek() == Some(b'.') {
+            self.offset += 1;
+            self.digits()?;
+        }
+        if let Some(b'e' | b'E') = self.peek() {
+            self.offset += 1;
+            if let Some(b'+' | b'-') = self.peek() {
+                self.offset += 1;
+            }
+            self.digits()?;
+        }
+        // Only ASCII was consumed.
+        let text = std::str::from_utf8(&self.source[start..self.offset]).unwrap_or_default();
+        Ok(text
+            .parse()
+            .map_or_else(|_| Json::Number(text.into()), Json::Integer))
+    }
+
+    fn hex_escape(&mut self) -> Result<u16, Error> {
+        let digits = self
+            .source
+            .get(self.offset..self.offset + 4)
+            // from_str_radix would also accept a leading sign.
+            .filter(|digits| digits.iter().all(u8::is_ascii_hexdigit))
+            .and_then(|digits| std::str::from_utf8(digits).ok())
+            .and_then(|digits| u16::from_str_radix(digits, 16).ok());
+        match digits {
+            Some(unit) => {
+                self.offset += 4;
+                Ok(unit)
+            }
+            None => self.error("expected four hex digits"),
+        }
+    }
+
+    fn string(&mut self) -> Result<Rc<str>, Error> {
+        self.expect(b'"')?;
+        let mut string = Vec::new();
+        loop {
+            let Some(byte) = self.peek() else {
+                return self.error("unterminated string");
+            };
+            match byte {
+                b'"' => {
+                    self.offset += 1;
+                    break;
+                }
+                b'\\' => {
+                    self.offset += 1;
+                    let escaped = match self.peek() {
+                        Some(b'"') => '"',
+                        Some(b'\\') => '\\',
+                        Some(b'/') => '/',
+                        Some(b'b') => '\u{8}',
+                        Some(b'f') => '\u{c}',
+                        Some(b'n') => '\n',
+                        Some(b'r') => '\r',
+                        Some(b't') => '\t',
+                        Some(b'u') => {
+                            self.offset += 1;
+                            let start = self.offset - 2;
+                            let high = self.hex_escape()?;
+                            let character = if (0xd800..0xdc00).contains(&high) {
+                                // A surrogate pair spans two escapes.
+                                if !self.source[self.offset..].starts_with(b"\\u") {
+                                    return self.error("expected a low surrogate");
+                                }
+                                self.offset += 2;
+                                let low = self.hex_escape()?;
+                                char::decode_utf16([high, low]).next().and_then(Result::ok)
+                            } else {
+                                char::from_u32(high.into())
+                            };
+                            let Some(character) = character else {
+                                self.offset = start;
+                                return self.error("invalid unicode escape");
+                            };
+                            string.extend_from_slice(character.encode_utf8(&mut [0; 4]).as_bytes());
+                            continue;
+                        }
+                        _ => return self.unexpected("an escape sequence"),
+                    };
+                    self.offset += 1;
+                    string.extend_from_slice(escaped.encode_utf8(&mut [0; 4]).as_bytes());
+                }
+                0..0x20 => return self.error("control character in string"),
+                _ => {
+                    string.push(byte);
+                    self.offset += 1;
+                }
+            }
+        }
+        // The source was a str, and escapes are always encoded as valid UTF-8.
+        Ok(String::from_utf8_lossy(&string).into())
+    }
+
+    fn array(&mut self) -> Result<Json, Error> {
+        self.expect(b'[')?;
+        let mut values = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.offset += 1;
+            return Ok(Json::Array(values));
+        }
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b']') => {
+                    self.offset += 1;
+                    return Ok(Json::Array(values));
+                }
+                _ => return self.unexpected("',' or ']'"),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, Error> {
+        self.expect(b'{')?;
+        let mut members = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.offset += 1;
+            return Ok(Json::Object(members));
+        }
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(b':')?;
+            members.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.offset += 1,
+                Some(b'}') => {
+                    self.offset += 1;
+                    return Ok(Json::Object(members));
+                }
+                _ => return self.unexpected("',' or '}'"),
+            }
+        }
+    }
+}
+
+pub fn parse(source: &str) -> Result<Json, Error> {
+    let mut parser = Parser {
+        source: source.as_bytes(),
+        offset: 0,
+        depth: 0,
+    };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.offset < source.len() {
+        return parser.unexpected("end of input");
+    }
+    Ok(value)
+}
+
+/// Writes `string` as a quoted JSON string.
+pub fn encode_string(output: &mut String, string: &str) {
+    output.push('"');
+    for character in string.chars() {
+        match character {
+            '"' => output.push_str("\\\""),
+            '\\' => output.push_str("\\\\"),
+            '\n' => output.push_str("\\n"),
+            '\r' => output.push_str("\\r"),
+            '\t' => output.push_str("\\t"),
+            '\0'..='\u{1f}' => {
+                let _ = write!(output, "\\u{:04x}", character as u32);
+            }
+            _ => output.push(character),
+        }
+    }
+    output.push('"');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(source: &str) -> Rc<str> {
+        match parse(source) {
+            Ok(Json::String(string)) => string,
+            result => panic!("expected a string from {source}, found {result:?}"),
+        }
+    }
+
+    fn error(source: &str) -> (usize, String) {
+        let error = parse(source).expect_err(source);
+        (error.offset, error.message)
+    }
+
+    #[test]
+    fn values() {
+        assert_eq!(
+            parse(r#" {"a": [null, true, false], "b": {}, "c": []} "#).unwrap(),
+            Json::Object(vec![
+                (
+                    "a".into(),
+                    Json::Array(vec![Json::Null, Json::Bool(true), Json::Bool(false)])
+                ),
+                ("b".into(), Json::Object(vec![])),
+                ("c".into(), Json::Array(vec![])),
+            ])
+        );
+    }
+
+    #[test]
+    fn numbers() {
+        assert_eq!(parse("0").unwrap(), Json::Integer(0));
+        assert_eq!(parse("-42").unwrap(), Json::Integer(-42));
+        assert_eq!(
+            parse("9223372036854775807").unwrap(),
+            Json::Integer(i64::MAX)
+        );
+        assert_eq!(
+            parse("9223372036854775808").unwrap(),
+            Json::Number("9223372036854775808".into())
+        );
+        assert_eq!(parse("1.5").unwrap(), Json::Number("1.5".into()));
+        assert_eq!(parse("-2E+10").unwrap(), Json::Number("-2E+10".into()));
+        assert_eq!(error("01"), (1, "expected end of input, found '1'".into()));
+        assert_eq!(
+            error("-"),
+            (1, "expected a digit, found end of input".into())
+        );
+        assert_eq!(
+            error("1."),
+            (2, "expected a digit, found end of input".into())
+        );
+        assert_eq!(
+            error("1e+"),
+            (3, "expected a digit, found end of input".into())
+        );
+        assert_eq!(error("+1"), (0, "expected a value, found '+'".into()));
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(&*string(r#""\"\\\/\b\f\n\r\t""#), "\"\\/\u{8}\u{c}\n\r\t");
+        assert_eq!(&*string(r#""\u00e9\u20AC""#), "é€");
+        assert_eq!(
+            error(r#""\x""#),
+            (2, "expected an escape sequence, found 'x'".into())
+        );
+        assert_eq!(error(r#""\u12""#), (3, "expected four hex digits".into()));
+        assert_eq!(error(r#""\u+abc""#), (3, "expected four hex digits".into()));
+        assert_eq!(error(r#""\u-abc""#), (3, "expected four hex digits".into()));
+        assert_eq!(error("\"a\nb\""), (2, "control character in string".into()));
+        assert_eq!(error(r#""abc"#), (4, "unterminated string".into()));
+    }
+
+    #[test]
+    fn surrogate_pairs() {
+        assert_eq!(&*string(r#""\ud83d\ude00""#), "😀");
+        assert_eq!(error(r#""\ud83d""#), (7, "expected a low surrogate".into()));
+        assert_eq!(
+            error(r#""x\ud83d\u0041""#),
+            (2, "invalid unicode escape".into())
+        );
+        assert_eq!(error(r#""\ude00""#), (1, "invalid unicode escape".into()));
+    }
+
+    #[test]
+    fn depth() {
+        let nested = |depth| "[".repeat(depth) + &"]".repeat(depth);
+        assert!(parse(&nested(MAX_DEPTH)).is_ok());
+        assert_eq!(
+            error(&nested(MAX_DEPTH + 1)),
+            (MAX_DEPTH, "nested too deeply".into())
+        );
+    }
+
+    #[test]
+    fn offsets() {
+        assert_eq!(
+            error(""),
+            (0, "expected a value, found end of input".into())
+        );
+        assert_eq!(error("[1 2]"), (3, "expected ',' or ']', found '2'".into()));
+        assert_eq!(error(r#"{"a" 1}"#), (5, "expected ':', found '1'".into()));
+        assert_eq!(error("{1: 2}"), (1, "expected '\"', found '1'".into()));
+        assert_eq!(error("[tru]"), (1, "expected true".into()));
+        assert_eq!(
+            error("[1] x"),
+            (4, "expected end of input, found 'x'".into())
+        );
+        // Offsets count bytes, not characters.
+        assert_eq!(
+            error(r#"["é" x]"#),
+            (6, "expected ',' or ']', found 'x'".into())
+        );
+    }
+
+    #[test]
+    fn encoding() {
+        let mut output = String::new();
+        encode_string(&mut output, "a\"b\\c\nd\u{1}é");
+        assert_eq!(output, r#""a\"b\\c\nd\u0001é""#);
+        assert_eq!(&*string(&output), "a\"b\\c\nd\u{1}é");
+    }
+}
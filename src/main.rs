@@ -1,7 +1,29 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::time::Instant;
 use std::{env, fs, rc::Rc};
 use wayland_client::Connection;
 
+mod backlight;
 mod bindings;
+mod color;
+mod command;
+mod event;
+mod filesystem;
+mod format;
+mod host;
+mod icon;
+mod image;
+mod json;
+mod net;
+mod netlink;
+mod pattern;
+mod power;
+mod render;
+mod signal;
+mod sys;
+mod timer;
+mod watch;
 mod wayland;
 
 #[derive(Clone)]
@@ -24,6 +46,16 @@ pub struct SurfaceConfig {
 pub enum WidgetRefreshRate {
     Timer(std::time::Duration),
     Framerate,
+    /// Redraw whenever this command prints a line.
+    Stream(Box<[Rc<str>]>),
+    /// Redraw whenever the file or directory at `path` changes,
+    /// and after `fallback` has passed without a redraw.
+    Watch {
+        path: Rc<str>,
+        fallback: Option<std::time::Duration>,
+    },
+    /// Redraw on multiples of this period of wall-clock time.
+    Aligned(std::time::Duration),
 }
 
 #[derive(Clone, Debug)]
@@ -37,43 +69,139 @@ pub struct UserWidget {
     pub title: Rc<str>,
     pub width: u32,
     pub refresh: WidgetRefreshRate,
+    /// Redraw immediately upon receiving `SIGRTMIN` plus this offset.
+    pub signal: Option<i64>,
     pub draw: espy::Function<'static>,
+    pub state: Rc<WidgetState>,
+}
+
+impl UserWidget {
+    /// When the widget will next need to be redrawn, if ever.
+    pub fn deadline(&self, now: Instant) -> Option<Instant> {
+        if self.state.dirty.get()
+            || self.state.output_ready.get()
+            || self.state.pixmap.borrow().is_none()
+        {
+            return Some(now);
+        }
+        match self.refresh {
+            WidgetRefreshRate::Framerate => Some(now),
+            // Widgets without a timer are only due once marked dirty.
+            _ => self.state.next_refresh.get(),
+        }
+    }
+
+    /// Whether the widget needs to be redrawn.
+    pub fn is_due(&self, now: Instant) -> bool {
+        self.deadline(now).is_some_and(|deadline| deadline <= now)
+    }
+}
+
+/// Runtime state shared by every copy of a widget.
+#[derive(Debug, Default)]
+pub struct WidgetState {
+    /// The widget's most recent rendering, reused until it's due for a refresh.
+    pub pixmap: RefCell<Option<tiny_skia::Pixmap>>,
+    /// `None` if the widget doesn't have a timer.
+    pub next_refresh: Cell<Option<Instant>>,
+    /// Set when something the widget depends on has changed outside of its refresh rate,
+    /// such as a watched file changing.
+    pub dirty: Cell<bool>,
+    /// Set when a command run by the widget finishes.
+    ///
+    /// Like `dirty`, but the redraw only updates what the previous draw showed,
+    /// so it doesn't begin a new cycle.
+    pub output_ready: Cell<bool>,
+    /// Counts the draws that took fresh samples, as opposed to those caused by `output_ready`.
+    ///
+    /// Samples taken again within a cycle replace that cycle's sample,
+    /// such as by `psybeam.history.push`.
+    pub cycle: Cell<u64>,
+    /// Samples recorded by `psybeam.history.push`, oldest first.
+    pub history: RefCell<VecDeque<i64>>,
+    /// The cycle in which the newest sample in `history` was recorded.
+    pub history_cycle: Cell<u64>,
+    /// CPU times read by `psybeam.sys.cpu`, total first.
+    pub cpu_times: RefCell<Samples<Vec<sys::CpuTimes>>>,
+    /// Counters read by `psybeam.net`, by interface name.
+    pub net_samples: RefCell<HashMap<Rc<str>, Samples<net::Sample>>>,
+    /// The most recent line printed by the widget's stream.
+    pub line: RefCell<Option<Rc<str>>>,
+    /// The timestamp of the frame the widget was last drawn for.
+    pub frame_time: Cell<u32>,
+}
+
+/// Readings of a counter taken by a widget, for measuring how it changes from one cycle
+/// to the next.
+#[derive(Debug)]
+pub struct Samples<T> {
+    cycle: u64,
+    previous: Option<T>,
+    current: Option<T>,
+}
+
+impl<T> Default for Samples<T> {
+    fn default() -> Self {
+        Self {
+            cycle: 0,
+            previous: None,
+            current: None,
+        }
+    }
+}
+
+impl<T: Clone> Samples<T> {
+    /// Records a reading taken during `cycle`,
+    /// returning the latest reading from an earlier cycle to measure it against.
+    pub fn record(&mut self, cycle: u64, sample: T) -> Option<T> {
+        if cycle != self.cycle {
+            self.cycle = cycle;
+            self.previous = self.current.take();
+        }
+        self.current = Some(sample);
+        self.previous.clone()
+    }
 }
 
 pub struct Psybeam {
     pub config: SurfaceConfig,
     pub running: bool,
+    /// Set after committing a frame, until the compositor is ready for another.
+    pub frame_pending: bool,
+    /// Forces every widget to be composited again, such as after a resize.
+    pub needs_redraw: bool,
+    /// The timestamp of the most recent frame callback, in milliseconds.
+    ///
+    /// The base is unspecified, but compositors generally use `CLOCK_MONOTONIC`,
+    /// which is also used for the first frame.
+    pub frame_time: u32,
     pub resources: wayland::PsybeamResources,
     pub layout: Box<[Widget]>,
-    pub swash_cache: cosmic_text::SwashCache,
-    pub font_system: cosmic_text::FontSystem,
+    pub renderer: render::Renderer,
 }
 
 impl Psybeam {
-    pub fn new(config: SurfaceConfig, layout: Box<[Widget]>) -> Self {
+    pub fn new(config: SurfaceConfig, layout: Box<[Widget]>, icon_theme: &str) -> Self {
         Self {
             config,
             running: true,
+            frame_pending: false,
+            needs_redraw: false,
+            frame_time: {
+                let now = rustix::time::clock_gettime(rustix::time::ClockId::Monotonic);
+                (now.tv_sec * 1000 + now.tv_nsec / 1_000_000) as u32
+            },
             resources: wayland::PsybeamResources::Partial(wayland::PsybeamPartial::default()),
             layout,
-            swash_cache: cosmic_text::SwashCache::new(),
-            font_system: cosmic_text::FontSystem::new(),
+            renderer: render::Renderer::new(icon_theme),
         }
     }
 }
 
-espy::extern_impl! {
-    #[espy(debug = "psybeam libraries")]
-    struct Libs {
-        std: espy::Value::borrow(&espystandard::Lib),
-        psybeam: espy::Value::borrow(&bindings::Lib),
-    }
-}
-
 fn main() -> anyhow::Result<()> {
     let path = env::args()
         .nth(1)
-        .ok_or_else(|| anyhow::anyhow!("expected one argument"))?;
+        .ok_or_else(|| anyhow::anyhow!("expected the path to a config script"))?;
     let source = fs::read_to_string(&path)?;
     // TODO: espy Errors need to implement Error.
     // TODO: shortcut function to skip lexer?
@@ -99,12 +227,28 @@ fn main() -> anyhow::Result<()> {
 
     let program = espy::Program::try_from(source.as_str()).unwrap();
     let function = program.eval().unwrap().into_function().unwrap();
-    let config = function
-        .piped(espy::Value::borrow(&Libs))
-        .eval()
-        .unwrap()
-        .into_tuple()
-        .unwrap();
+    // Arguments given after the script's path, such as `psybeam config.espy laptop`,
+    // with no arguments represented by unit.
+    let args = host::args();
+    let args = if args.is_empty() {
+        espy::Value::Unit
+    } else {
+        espy::Value::Tuple(
+            args.into_iter()
+                .map(espy::Value::String)
+                .collect::<Rc<[_]>>()
+                .into(),
+        )
+    };
+    let libs = espy::Value::Tuple(
+        [
+            (Rc::from("std"), espy::Value::borrow(&espystandard::Lib)),
+            (Rc::from("psybeam"), espy::Value::borrow(&bindings::Lib)),
+            (Rc::from("args"), args),
+        ]
+        .into(),
+    );
+    let config = function.piped(libs).eval().unwrap().into_tuple().unwrap();
     let surface = config.find_value("surface").cloned().map_or(
         SurfaceConfig {
             height: 32,
@@ -146,7 +290,29 @@ fn main() -> anyhow::Result<()> {
         .map(|value| value.downcast_extern::<Widget>().unwrap().clone())
         .collect::<Box<[Widget]>>();
 
-    let mut psybeam = Psybeam::new(surface, layout);
+    let icon_theme = config
+        .find_value("icon_theme")
+        .cloned()
+        // hicolor is every theme's fallback, so it's a safe default.
+        .map_or(Rc::from("hicolor"), |theme| theme.into_str().unwrap());
+
+    for widget in &layout {
+        if let Widget::User(widget) = widget {
+            match &widget.refresh {
+                WidgetRefreshRate::Stream(argv) => command::stream(&widget.state, argv.clone()),
+                WidgetRefreshRate::Watch { path, .. } => watch::watch(&widget.state, path.clone()),
+                WidgetRefreshRate::Aligned(period) => timer::align(&widget.state, *period),
+                WidgetRefreshRate::Timer(_) | WidgetRefreshRate::Framerate => {}
+            }
+            if let Some(offset) = widget.signal
+                && let Err(e) = signal::watch(&widget.state, offset)
+            {
+                eprintln!("widget {} can't be refreshed by signal: {e}", widget.title);
+            }
+        }
+    }
+
+    let mut psybeam = Psybeam::new(surface, layout, &icon_theme);
 
     let connection = Connection::connect_to_env()?;
     let mut event_queue = connection.new_event_queue();
@@ -155,8 +321,28 @@ fn main() -> anyhow::Result<()> {
     let _ = display.get_registry(&qh, ());
 
     while psybeam.running {
-        event_queue.blocking_dispatch(&mut psybeam)?;
+        event_queue.dispatch_pending(&mut psybeam)?;
+        psybeam.redraw(&qh);
+        event_queue.flush()?;
+        let Some(guard) = event_queue.prepare_read() else {
+            continue;
+        };
+        let deadline = psybeam
+            .next_deadline()
+            .into_iter()
+            .chain(command::next_deadline())
+            .min();
+        if event::wait(guard.connection_fd(), deadline)? {
+            guard.read()?;
+        } else {
+            drop(guard);
+        }
+        command::update();
+        watch::update();
+        signal::update();
+        timer::update();
     }
+    command::clear();
 
     Ok(())
 }
@@ -0,0 +1,316 @@
+//! Network interfaces, from /sys/class/net and `NETLINK_ROUTE`, for `psybeam.net`.
+//!
+//! Throughput is measured against an earlier [`Sample`], in the same way as CPU usage,
+//! so a widget refreshing every second sees the rate over the last second.
+
+use crate::netlink::{self, NLM_F_DUMP};
+use crate::sys;
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+use std::{fs, io};
+
+const RTM_NEWADDR: u16 = 20;
+const RTM_GETADDR: u16 = 22;
+
+const IFA_ADDRESS: u16 = 1;
+const IFA_LOCAL: u16 = 2;
+
+#[derive(Debug)]
+pub struct Interface {
+    pub name: Rc<str>,
+    /// The kernel's operational state, such as "up", "down", "dormant", or "unknown".
+    pub state: Rc<str>,
+    pub mac: Option<Rc<str>>,
+    pub wireless: bool,
+    /// IPv4 and IPv6 addresses in CIDR notation, such as "192.168.1.2/24".
+    pub addresses: Vec<Rc<str>>,
+    pub rx_bytes: i64,
+    pub tx_bytes: i64,
+    /// Bytes per second received since the sample given to [`Interface::measure`],
+    /// or zero if not measured.
+    pub rx_rate: i64,
+    /// Bytes per second sent since the sample given to [`Interface::measure`],
+    /// or zero if not measured.
+    pub tx_rate: i64,
+    time: Instant,
+}
+
+/// An interface's counters at a point in time, for measuring throughput.
+#[derive(Clone, Copy, Debug)]
+pub struct Sample {
+    time: Instant,
+    rx_bytes: i64,
+    tx_bytes: i64,
+}
+
+impl Interface {
+    pub fn sample(&self) -> Sample {
+        Sample {
+            time: self.time,
+            rx_bytes: self.rx_bytes,
+            tx_bytes: self.tx_bytes,
+        }
+    }
+
+    /// Measures throughput since `last`, an earlier sample of this interface.
+    pub fn measure(&mut self, last: &Sample) {
+        let seconds = self.time.duration_since(last.time).as_secs_f64();
+        self.rx_rate = rate(self.rx_bytes, last.rx_bytes, seconds);
+        self.tx_rate = rate(self.tx_bytes, last.tx_bytes, seconds);
+    }
+}
+
+fn rate(bytes: i64, last: i64, seconds: f64) -> i64 {
+    // Counters are reset when a driver is reloaded.
+    if bytes < last || seconds <= 0.0 {
+        0
+    } else {
+        ((bytes - last) as f64 / seconds) as i64
+    }
+}
+
+/// Addresses of every interface, keyed by interface index.
+fn addresses() -> io::Result<HashMap<u32, Vec<Rc<str>>>> {
+    let socket = netlink::Socket::route()?;
+    // An empty ifaddrmsg, with AF_UNSPEC to dump every address family.
+    let messages = socket.request(RTM_GETADDR, NLM_F_DUMP, &[0; 8])?;
+    let mut addresses = HashMap::<u32, Vec<Rc<str>>>::new();
+    for message in messages {
+        if message.kind != RTM_NEWADDR || message.payload.len() < 8 {
+            continue;
+        }
+        let family = i32::from(message.payload[0]);
+        let prefix = message.payload[1];
+        let index = u32::from_ne_bytes([
+            message.payload[4],
+            message.payload[5],
+            message.payload[6],
+            message.payload[7],
+        ]);
+        let (mut local, mut address) = (None, None);
+        for (kind, payload) in netlink::attributes(&message.payload[8..]) {
+            match kind {
+                IFA_LOCAL => local = Some(payload),
+                IFA_ADDRESS => address = Some(payload),
+                _ => {}
+            }
+        }
+        // For point-to-point links, IFA_ADDRESS is the other end.
+        let address = match (family, local.or(address)) {
+            (libc::AF_INET, Some(&[a, b, c, d])) => Ipv4Addr::new(a, b, c, d).to_string(),
+            (libc::AF_INET6, Some(address)) => match <[u8; 16]>::try_from(address) {
+                Ok(address) => Ipv6Addr::from(address).to_string(),
+                Err(_) => continue,
+            },
+            _ => continue,
+        };
+        addresses
+            .entry(index)
+            .or_default()
+            .push(format!("{address}/{prefix}").into());
+    }
+    Ok(addresses)
+}
+
+fn read(path: &Path, property: &str) -> Option<String> {
+    fs::read_to_string(path.join(property))
+        .ok()
+        .map(|value| value.trim().to_owned())
+}
+
+fn read_interface(
+    name: &str,
+    path: &Path,
+    addresses: &mut HashMap<u32, Vec<Rc<str>>>,
+    now: Instant,
+) -> Interface {
+    let number = |property: &str| {
+        read(path, property)
+            .and_then(|value| value.parse::<i64>().ok())
+            .unwrap_or(0)
+    };
+    let name: Rc<str> = name.into();
+    Interface {
+        state: read(path, "operstate").map_or_else(|| "unknown".into(), Rc::from),
+        // Interfaces without a hardware address, such as loopback and tunnels,
+        // report an empty or all-zero one.
+        mac: read(path, "address")
+            .filter(|mac| mac.chars().any(|c| c.is_ascii_hexdigit() && c != '0'))
+            .map(Rc::from),
+        wireless: path.join("wireless").is_dir() || path.join("phy80211").exists(),
+        addresses: u32::try_from(number("ifindex"))
+            .ok()
+            .and_then(|index| addresses.remove(&index))
+            .unwrap_or_default(),
+        name,
+        rx_bytes: number("statistics/rx_bytes"),
+        tx_bytes: number("statistics/tx_bytes"),
+        rx_rate: 0,
+        tx_rate: 0,
+        time: now,
+    }
+}
+
+/// Every network interface, sorted by name.
+pub fn interfaces() -> io::Result<Vec<Interface>> {
+    let mut entries = fs::read_dir(sys::sysfs("class/net"))?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((entry.file_name().into_string().ok()?, entry.path()))
+        })
+        .collect::<Vec<(String, PathBuf)>>();
+    entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut addresses = addresses()?;
+    let now = Instant::now();
+    Ok(entries
+        .iter()
+        .map(|(name, path)| read_interface(name, path, &mut addresses, now))
+        .collect())
+}
+
+/// A single network interface by name, such as "wlan0".
+pub fn interface(name: &str) -> io::Result<Interface> {
+    let path = sys::sysfs(Path::new("class/net").join(name));
+    if !path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no network interface named {name}"),
+        ));
+    }
+    Ok(read_interface(
+        name,
+        &path,
+        &mut addresses()?,
+        Instant::now(),
+    ))
+}
+
+const NL80211_CMD_GET_INTERFACE: u8 = 5;
+const NL80211_CMD_GET_STATION: u8 = 17;
+
+const NL80211_ATTR_IFINDEX: u16 = 3;
+const NL80211_ATTR_IFNAME: u16 = 4;
+const NL80211_ATTR_MAC: u16 = 6;
+const NL80211_ATTR_STA_INFO: u16 = 21;
+const NL80211_ATTR_WIPHY_FREQ: u16 = 38;
+const NL80211_ATTR_SSID: u16 = 52;
+
+const NL80211_STA_INFO_SIGNAL: u16 = 7;
+const NL80211_STA_INFO_TX_BITRATE: u16 = 8;
+
+const NL80211_RATE_INFO_BITRATE: u16 = 1;
+const NL80211_RATE_INFO_BITRATE32: u16 = 5;
+
+/// The connection of a wireless interface, from nl80211.
+#[derive(Debug)]
+pub struct Wireless {
+    pub interface: Rc<str>,
+    /// The name of the network, if connected.
+    pub ssid: Option<Rc<str>>,
+    /// The hardware address of the access point.
+    pub bssid: Option<Rc<str>>,
+    /// dBm
+    pub signal: Option<i64>,
+    /// kbit/s, for transmitting to the access point.
+    pub bitrate: Option<i64>,
+    /// MHz
+    pub frequency: Option<i64>,
+}
+
+impl Wireless {
+    /// Signal strength as a percentage, scaled linearly from -100 dBm to -50 dBm.
+    pub fn quality(&self) -> Option<i64> {
+        Some(((self.signal? + 100) * 2).clamp(0, 100))
+    }
+}
+
+fn u32_attribute(payload: &[u8]) -> Option<u32> {
+    Some(u32::from_ne_bytes(payload.get(..4)?.try_into().ok()?))
+}
+
+fn find_attribute(attributes: &[u8], kind: u16) -> Option<&[u8]> {
+    netlink::attributes(attributes).find_map(|(k, payload)| (k == kind).then_some(payload))
+}
+
+/// Fills in the signal strength and bitrate of a connection from its access point's station info.
+fn station(
+    socket: &netlink::Socket,
+    family: u16,
+    index: u32,
+    wireless: &mut Wireless,
+) -> io::Result<()> {
+    let mut request = Vec::new();
+    netlink::push_attribute(&mut request, NL80211_ATTR_IFINDEX, &index.to_ne_bytes());
+    // A station interface only has one station: the access point it's associated with.
+    let replies = socket.generic_request(family, NL80211_CMD_GET_STATION, NLM_F_DUMP, &request)?;
+    let Some(reply) = replies.first() else {
+        return Ok(());
+    };
+    wireless.bssid = find_attribute(reply, NL80211_ATTR_MAC).map(|mac| {
+        mac.iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect::<Vec<_>>()
+            .join(":")
+            .into()
+    });
+    let Some(info) = find_attribute(reply, NL80211_ATTR_STA_INFO) else {
+        return Ok(());
+    };
+    wireless.signal = find_attribute(info, NL80211_STA_INFO_SIGNAL)
+        .and_then(|signal| Some(i64::from(*signal.first()? as i8)));
+    // Rates are given in units of 100 kbit/s; the 16-bit attribute overflows past 6.5 Gbit/s.
+    wireless.bitrate = find_attribute(info, NL80211_STA_INFO_TX_BITRATE).and_then(|rate| {
+        let units = find_attribute(rate, NL80211_RATE_INFO_BITRATE32)
+            .and_then(u32_attribute)
+            .or_else(|| {
+                let bitrate = find_attribute(rate, NL80211_RATE_INFO_BITRATE)?;
+                Some(u16::from_ne_bytes(bitrate.get(..2)?.try_into().ok()?).into())
+            })?;
+        Some(i64::from(units) * 100)
+    });
+    Ok(())
+}
+
+/// Every wireless interface, sorted by name.
+///
+/// Machines without any wireless hardware may not have nl80211 at all,
+/// in which case there are simply no wireless interfaces.
+pub fn wireless() -> io::Result<Vec<Wireless>> {
+    let socket = netlink::Socket::generic()?;
+    let family = match socket.family("nl80211") {
+        Ok(family) => family,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    let replies = socket.generic_request(family, NL80211_CMD_GET_INTERFACE, NLM_F_DUMP, &[])?;
+    let mut interfaces = Vec::new();
+    for reply in replies {
+        // P2P devices have neither an index nor a name.
+        let (Some(index), Some(name)) = (
+            find_attribute(&reply, NL80211_ATTR_IFINDEX).and_then(u32_attribute),
+            find_attribute(&reply, NL80211_ATTR_IFNAME),
+        ) else {
+            continue;
+        };
+        let mut wireless = Wireless {
+            interface: String::from_utf8_lossy(name.strip_suffix(b"\0").unwrap_or(name)).into(),
+            ssid: find_attribute(&reply, NL80211_ATTR_SSID)
+                .map(|ssid| String::from_utf8_lossy(ssid).into()),
+            bssid: None,
+            signal: None,
+            bitrate: None,
+            frequency: find_attribute(&reply, NL80211_ATTR_WIPHY_FREQ)
+                .and_then(u32_attribute)
+                .map(i64::from),
+        };
+        if wireless.ssid.is_some() {
+            station(&socket, family, index, &mut wireless)?;
+        }
+        interfaces.push(wireless);
+    }
+    interfaces.sort_by(|a, b| a.interface.cmp(&b.interface));
+    Ok(interfaces)
+}
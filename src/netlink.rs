@@ -0,0 +1,186 @@
+//! Just enough netlink to query the kernel's routing and nl80211 families.
+//!
+//! Requests are made synchronously: the kernel answers them immediately,
+//! so there's no need to involve the event loop.
+
+use rustix::fd::OwnedFd;
+use rustix::net::{AddressFamily, RecvFlags, SendFlags, SocketFlags, SocketType, netlink};
+use std::cell::Cell;
+use std::io;
+
+const HEADER_LENGTH: usize = 16;
+
+pub const NLM_F_REQUEST: u16 = 0x1;
+pub const NLM_F_ACK: u16 = 0x4;
+pub const NLM_F_DUMP: u16 = 0x300;
+
+const NLMSG_ERROR: u16 = 2;
+const NLMSG_DONE: u16 = 3;
+
+const GENL_ID_CTRL: u16 = 0x10;
+const CTRL_CMD_GETFAMILY: u8 = 3;
+const CTRL_ATTR_FAMILY_ID: u16 = 1;
+const CTRL_ATTR_FAMILY_NAME: u16 = 2;
+
+/// Messages and attributes are padded to four bytes.
+fn align(length: usize) -> usize {
+    (length + 3) & !3
+}
+
+pub struct Message {
+    pub kind: u16,
+    /// Everything after the netlink header.
+    pub payload: Vec<u8>,
+}
+
+pub struct Socket {
+    fd: OwnedFd,
+    sequence: Cell<u32>,
+}
+
+impl Socket {
+    fn new(protocol: Option<rustix::net::Protocol>) -> io::Result<Self> {
+        Ok(Self {
+            fd: rustix::net::socket_with(
+                AddressFamily::NETLINK,
+                SocketType::RAW,
+                SocketFlags::CLOEXEC,
+                protocol,
+            )?,
+            sequence: Cell::new(0),
+        })
+    }
+
+    /// A socket for `NETLINK_ROUTE`, which covers interfaces and their addresses.
+    pub fn route() -> io::Result<Self> {
+        Self::new(None)
+    }
+
+    /// A socket for `NETLINK_GENERIC`, which covers families such as nl80211.
+    pub fn generic() -> io::Result<Self> {
+        Self::new(Some(netlink::GENERIC))
+    }
+
+    /// Sends a request, and collects every reply until the kernel acknowledges it
+    /// or finishes dumping.
+    pub fn request(&self, kind: u16, flags: u16, payload: &[u8]) -> io::Result<Vec<Message>> {
+        let sequence = self.sequence.get().wrapping_add(1);
+        self.sequence.set(sequence);
+
+        let mut request = Vec::with_capacity(HEADER_LENGTH + payload.len());
+        request.extend_from_slice(&((HEADER_LENGTH + payload.len()) as u32).to_ne_bytes());
+        request.extend_from_slice(&kind.to_ne_bytes());
+        request.extend_from_slice(&(flags | NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        request.extend_from_slice(&sequence.to_ne_bytes());
+        // The kernel assigns our port ID.
+        request.extend_from_slice(&0u32.to_ne_bytes());
+        request.extend_from_slice(payload);
+        rustix::net::send(&self.fd, &request, SendFlags::empty())?;
+
+        let mut messages = Vec::new();
+        let mut buffer = vec![0; 32768];
+        loop {
+            let (length, _) = rustix::net::recv(&self.fd, &mut buffer[..], RecvFlags::empty())?;
+            let mut datagram = &buffer[..length];
+            while datagram.len() >= HEADER_LENGTH {
+                let u16_at = |i: usize| u16::from_ne_bytes([datagram[i], datagram[i + 1]]);
+                let u32_at = |i: usize| {
+                    u32::from_ne_bytes([
+                        datagram[i],
+                        datagram[i + 1],
+                        datagram[i + 2],
+                        datagram[i + 3],
+                    ])
+                };
+                let message_length = (u32_at(0) as usize).clamp(HEADER_LENGTH, datagram.len());
+                let message_kind = u16_at(4);
+                let message_sequence = u32_at(8);
+                let payload = &datagram[HEADER_LENGTH..message_length];
+                datagram = &datagram[align(message_length).min(datagram.len())..];
+                // Stale replies to an earlier request, such as a trailing acknowledgement.
+                if message_sequence != sequence {
+                    continue;
+                }
+                match message_kind {
+                    NLMSG_DONE => return Ok(messages),
+                    NLMSG_ERROR => {
+                        let error = payload.get(..4).map_or(0, |error| {
+                            i32::from_ne_bytes([error[0], error[1], error[2], error[3]])
+                        });
+                        return if error == 0 {
+                            Ok(messages)
+                        } else {
+                            Err(io::Error::from_raw_os_error(-error))
+                        };
+                    }
+                    _ => messages.push(Message {
+                        kind: message_kind,
+                        payload: payload.into(),
+                    }),
+                }
+            }
+        }
+    }
+
+    /// Sends a command to a generic netlink family, and returns the attributes of each reply.
+    pub fn generic_request(
+        &self,
+        family: u16,
+        command: u8,
+        flags: u16,
+        attributes: &[u8],
+    ) -> io::Result<Vec<Vec<u8>>> {
+        // genlmsghdr: the command, its version, and two reserved bytes.
+        let mut payload = vec![command, 1, 0, 0];
+        payload.extend_from_slice(attributes);
+        Ok(self
+            .request(family, flags, &payload)?
+            .into_iter()
+            .filter(|message| message.kind == family && message.payload.len() >= 4)
+            .map(|message| message.payload[4..].into())
+            .collect())
+    }
+
+    /// Resolves the name of a generic netlink family, such as "nl80211", to its message type.
+    pub fn family(&self, name: &str) -> io::Result<u16> {
+        let mut request = Vec::new();
+        push_attribute(
+            &mut request,
+            CTRL_ATTR_FAMILY_NAME,
+            format!("{name}\0").as_bytes(),
+        );
+        self.generic_request(GENL_ID_CTRL, CTRL_CMD_GETFAMILY, 0, &request)?
+            .iter()
+            .find_map(|reply| {
+                attributes(reply)
+                    .find(|(kind, _)| *kind == CTRL_ATTR_FAMILY_ID)
+                    .and_then(|(_, id)| Some(u16::from_ne_bytes(id.try_into().ok()?)))
+            })
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    format!("no generic netlink family named {name}"),
+                )
+            })
+    }
+}
+
+/// Iterates over the (type, payload) pairs of a sequence of attributes.
+pub fn attributes(mut bytes: &[u8]) -> impl Iterator<Item = (u16, &[u8])> {
+    std::iter::from_fn(move || {
+        let length = u16::from_ne_bytes([*bytes.first()?, *bytes.get(1)?]) as usize;
+        let kind = u16::from_ne_bytes([*bytes.get(2)?, *bytes.get(3)?]);
+        let payload = bytes.get(4..length)?;
+        bytes = bytes.get(align(length)..).unwrap_or_default();
+        // The upper bits flag nested and byte-order-swapped attributes.
+        Some((kind & 0x3fff, payload))
+    })
+}
+
+/// Appends an attribute to a request.
+pub fn push_attribute(request: &mut Vec<u8>, kind: u16, payload: &[u8]) {
+    request.extend_from_slice(&((4 + payload.len()) as u16).to_ne_bytes());
+    request.extend_from_slice(&kind.to_ne_bytes());
+    request.extend_from_slice(payload);
+    request.resize(align(request.len()), 0);
+}
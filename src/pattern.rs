@@ -0,0 +1,30 @@
+//! Compiled regular expressions for `psybeam.regex`.
+//!
+//! Scripts pass patterns as strings on every refresh,
+//! so each pattern is compiled once and kept for the rest of the run.
+
+use regex::Regex;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+/// Scripts building patterns from changing input could otherwise grow the cache forever.
+const MAX_CACHED: usize = 256;
+
+thread_local! {
+    static CACHE: RefCell<HashMap<Rc<str>, Rc<Regex>>> = RefCell::default();
+}
+
+pub fn compile(pattern: &str) -> Result<Rc<Regex>, regex::Error> {
+    if let Some(regex) = CACHE.with_borrow(|cache| cache.get(pattern).cloned()) {
+        return Ok(regex);
+    }
+    let regex = Rc::new(Regex::new(pattern)?);
+    CACHE.with_borrow_mut(|cache| {
+        if cache.len() >= MAX_CACHED {
+            cache.clear();
+        }
+        cache.insert(pattern.into(), regex.clone());
+    });
+    Ok(regex)
+}
@@ -0,0 +1,223 @@
+//! Batteries and other power supplies, from /sys/class/power_supply.
+//!
+//! Drivers report either energy (µWh) and power (µW), or charge (µAh) and current (µA);
+//! the latter are converted using the battery's voltage so that scripts only deal with energy.
+
+use crate::sys;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{fs, io};
+
+#[derive(Debug)]
+pub struct Battery {
+    pub name: Rc<str>,
+    /// Percent, from 0 to 100.
+    pub capacity: Option<i64>,
+    /// "charging", "discharging", "full", "not charging", or "unknown".
+    pub status: Rc<str>,
+    /// µWh
+    pub energy_now: Option<i64>,
+    /// µWh
+    pub energy_full: Option<i64>,
+    /// µW, always positive regardless of the direction of flow.
+    pub power: Option<i64>,
+}
+
+impl Battery {
+    /// Seconds until the battery is empty, if it's discharging.
+    pub fn time_to_empty(&self) -> Option<i64> {
+        let power = self.power.filter(|power| *power > 0)?;
+        (&*self.status == "discharging").then_some(self.energy_now? * 3600 / power)
+    }
+
+    /// Seconds until the battery is full, if it's charging.
+    pub fn time_to_full(&self) -> Option<i64> {
+        let power = self.power.filter(|power| *power > 0)?;
+        (&*self.status == "charging")
+            .then_some((self.energy_full? - self.energy_now?).max(0) * 3600 / power)
+    }
+}
+
+struct Supply {
+    path: PathBuf,
+}
+
+impl Supply {
+    fn read(&self, property: &str) -> Option<String> {
+        fs::read_to_string(self.path.join(property))
+            .ok()
+            .map(|value| value.trim().to_owned())
+    }
+
+    fn number(&self, property: &str) -> Option<i64> {
+        self.read(property)?.parse().ok()
+    }
+
+    fn battery(&self, name: &str) -> Battery {
+        let voltage = self.number("voltage_now");
+        // Converts µAh or µA to µWh or µW.
+        let energy = |energy: &str, charge: &str| {
+            self.number(energy).or_else(|| {
+                let product = i128::from(self.number(charge)?) * i128::from(voltage?);
+                i64::try_from(product / 1_000_000).ok()
+            })
+        };
+        let energy_now = energy("energy_now", "charge_now");
+        let energy_full = energy("energy_full", "charge_full");
+        let power = energy("power_now", "current_now").map(i64::abs);
+        Battery {
+            name: name.into(),
+            capacity: self.number("capacity").or_else(|| {
+                let full = energy_full.filter(|full| *full > 0)?;
+                Some(energy_now? * 100 / full)
+            }),
+            status: self
+                .read("status")
+                .map_or_else(|| "unknown".into(), |status| status.to_lowercase().into()),
+            energy_now,
+            energy_full,
+            power,
+        }
+    }
+}
+
+/// The supplies in a directory laid out like /sys/class/power_supply, sorted by name.
+fn supplies(root: &Path) -> io::Result<impl Iterator<Item = (String, Supply)>> {
+    let mut supplies = fs::read_dir(root)?
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            Some((
+                entry.file_name().into_string().ok()?,
+                Supply { path: entry.path() },
+            ))
+        })
+        .collect::<Vec<_>>();
+    supplies.sort_by(|(a, _), (b, _)| a.cmp(b));
+    Ok(supplies.into_iter())
+}
+
+fn read_batteries(root: &Path) -> io::Result<Vec<Battery>> {
+    Ok(supplies(root)?
+        .filter(|(_, supply)| {
+            supply.read("type").as_deref() == Some("Battery")
+                && supply.read("scope").as_deref() != Some("Device")
+        })
+        .map(|(name, supply)| supply.battery(&name))
+        .collect())
+}
+
+fn read_battery(root: &Path, name: &str) -> io::Result<Battery> {
+    let path = root.join(name);
+    if !path.is_dir() {
+        return Err(io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("no power supply named {name}"),
+        ));
+    }
+    Ok(Supply { path }.battery(name))
+}
+
+fn read_ac_online(root: &Path) -> io::Result<bool> {
+    Ok(supplies(root)?.any(|(_, supply)| {
+        supply.read("type").as_deref() != Some("Battery")
+            && supply.read("scope").as_deref() != Some("Device")
+            && supply.number("online") == Some(1)
+    }))
+}
+
+fn root() -> PathBuf {
+    sys::sysfs("class/power_supply")
+}
+
+/// Every power supply of type "Battery", sorted by name.
+///
+/// Peripherals such as mice and headsets also report themselves as batteries,
+/// but are marked with a scope of "Device" and are skipped.
+pub fn batteries() -> io::Result<Vec<Battery>> {
+    read_batteries(&root())
+}
+
+/// A single power supply by name, such as "BAT0".
+pub fn battery(name: &str) -> io::Result<Battery> {
+    read_battery(&root(), name)
+}
+
+/// Whether the system is being powered externally,
+/// by an AC adapter or a USB charger, rather than running on its batteries.
+pub fn ac_online() -> io::Result<bool> {
+    read_ac_online(&root())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUPPLIES: &str = "sys/class/power_supply";
+
+    #[test]
+    fn skips_peripherals() {
+        let batteries = read_batteries(&sys::fixture(SUPPLIES)).unwrap();
+        assert_eq!(
+            batteries
+                .iter()
+                .map(|battery| &*battery.name)
+                .collect::<Vec<_>>(),
+            ["BAT0", "BAT1"]
+        );
+        // Peripherals can still be read by name.
+        let mouse = read_battery(&sys::fixture(SUPPLIES), "hidpp_battery_0").unwrap();
+        assert_eq!(mouse.capacity, Some(80));
+        assert!(read_battery(&sys::fixture(SUPPLIES), "BAT2").is_err());
+    }
+
+    #[test]
+    fn energy() {
+        let battery = read_battery(&sys::fixture(SUPPLIES), "BAT0").unwrap();
+        assert_eq!(&*battery.status, "discharging");
+        assert_eq!(battery.capacity, Some(60));
+        assert_eq!(battery.energy_now, Some(30_000_000));
+        assert_eq!(battery.energy_full, Some(50_000_000));
+        assert_eq!(battery.power, Some(10_000_000));
+        assert_eq!(battery.time_to_empty(), Some(3 * 3600));
+        assert_eq!(battery.time_to_full(), None);
+    }
+
+    #[test]
+    fn charge_is_converted_to_energy() {
+        // 2 Ah of 4 Ah at 12 V, charging at 1 A (reported as negative current).
+        let battery = read_battery(&sys::fixture(SUPPLIES), "BAT1").unwrap();
+        assert_eq!(&*battery.status, "charging");
+        assert_eq!(battery.energy_now, Some(24_000_000));
+        assert_eq!(battery.energy_full, Some(48_000_000));
+        assert_eq!(battery.power, Some(12_000_000));
+        // Without a capacity file, it's worked out from the energy.
+        assert_eq!(battery.capacity, Some(50));
+        assert_eq!(battery.time_to_full(), Some(2 * 3600));
+        assert_eq!(battery.time_to_empty(), None);
+    }
+
+    #[test]
+    fn time_remaining_needs_power() {
+        let battery = Battery {
+            name: "BAT0".into(),
+            capacity: Some(100),
+            status: "charging".into(),
+            energy_now: Some(50_000_000),
+            energy_full: Some(48_000_000),
+            power: Some(0),
+        };
+        assert_eq!(battery.time_to_full(), None);
+        // Batteries can report more energy than their full capacity while topping off.
+        let battery = Battery {
+            power: Some(1_000_000),
+            ..battery
+        };
+        assert_eq!(battery.time_to_full(), Some(0));
+    }
+
+    #[test]
+    fn usb_charger_counts_as_ac() {
+        // The AC adapter is unplugged, but the laptop is charging over USB-C.
+        assert!(read_ac_online(&sys::fixture(SUPPLIES)).unwrap());
+    }
+}
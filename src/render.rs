@@ -0,0 +1,390 @@
+use crate::{UserWidget, WidgetRefreshRate, bindings, command, icon, image};
+use cosmic_text::{Attrs, Buffer, Color, Metrics};
+use std::cell::RefCell;
+use std::f32::consts::FRAC_PI_2;
+use std::path::Path;
+use std::time::Instant;
+use tiny_skia::{
+    FillRule, Paint, PathBuilder, Pixmap, PixmapPaint, PremultipliedColorU8, Rect, Stroke,
+    Transform,
+};
+
+thread_local! {
+    static DRAWING: RefCell<Option<UserWidget>> = const { RefCell::new(None) };
+}
+
+/// The widget whose draw function is being evaluated, if any.
+///
+/// Bindings with per-widget state, such as commands and history, are attributed to it.
+pub fn drawing() -> Option<UserWidget> {
+    DRAWING.with_borrow(Clone::clone)
+}
+
+pub struct Renderer {
+    pub swash_cache: cosmic_text::SwashCache,
+    pub font_system: cosmic_text::FontSystem,
+    pub images: image::ImageCache,
+    pub icons: icon::IconThemes,
+}
+
+impl Renderer {
+    pub fn new(icon_theme: &str) -> Self {
+        Self {
+            swash_cache: cosmic_text::SwashCache::new(),
+            font_system: cosmic_text::FontSystem::new(),
+            images: image::ImageCache::default(),
+            icons: icon::IconThemes::new(icon_theme),
+        }
+    }
+
+    /// Redraws the widget into its cached pixmap if it's due.
+    pub fn refresh(&mut self, widget: &UserWidget, height: u32, frame_time: u32) {
+        let now = Instant::now();
+        if !widget.is_due(now) {
+            return;
+        }
+        let state = &widget.state;
+        // Redrawing only to show the output of commands started by the previous draw
+        // shouldn't record a second sample for the same period.
+        let resample = state.output_ready.get()
+            && !state.dirty.get()
+            && state.pixmap.borrow().is_some()
+            && !matches!(widget.refresh, WidgetRefreshRate::Framerate)
+            && state
+                .next_refresh
+                .get()
+                .is_none_or(|next_refresh| next_refresh > now);
+        if !resample {
+            state.cycle.set(state.cycle.get() + 1);
+        }
+        // Redraws caused by dirtiness shouldn't throw off the widget's timer.
+        if widget
+            .state
+            .next_refresh
+            .get()
+            .is_none_or(|next_refresh| next_refresh <= now)
+        {
+            widget.state.next_refresh.set(match widget.refresh {
+                WidgetRefreshRate::Timer(duration) => Some(now + duration),
+                WidgetRefreshRate::Watch { fallback, .. } => {
+                    fallback.map(|fallback| now + fallback)
+                }
+                WidgetRefreshRate::Framerate
+                | WidgetRefreshRate::Stream(_)
+                | WidgetRefreshRate::Aligned(_) => None,
+            });
+        }
+        widget.state.dirty.set(false);
+        widget.state.output_ready.set(false);
+        widget.state.frame_time.set(frame_time);
+        DRAWING.set(Some(widget.clone()));
+        let pixmap = self.widget(widget, height);
+        DRAWING.set(None);
+        command::forget_unused(&widget.state);
+        *widget.state.pixmap.borrow_mut() = pixmap;
+    }
+
+    /// Evaluates a widget's draw function and renders the resulting instructions
+    /// into a pixmap covering the widget's region of the bar.
+    fn widget(&mut self, widget: &UserWidget, height: u32) -> Option<Pixmap> {
+        let mut pixmap = Pixmap::new(widget.width, height)?;
+        let mut draw = |instruction: &espy::Value| self.instruction(&mut pixmap, instruction);
+        match widget.draw.clone().eval() {
+            // Unit represents no drawing instructions.
+            Ok(espy::Value::Unit) => (),
+            Ok(espy::Value::Tuple(instructions)) => instructions.values().for_each(&mut draw),
+            Ok(instruction) => draw(&instruction),
+            Err(e) => {
+                eprintln!("widget renderer failed: {e:?}");
+            }
+        }
+        Some(pixmap)
+    }
+
+    fn instruction(&mut self, pixmap: &mut Pixmap, instruction: &espy::Value) {
+        if let Some(label) = instruction.downcast_extern::<bindings::Label>() {
+            self.label(pixmap, label);
+        } else if let Some(image) = instruction.downcast_extern::<bindings::Image>() {
+            self.image(pixmap, image);
+        } else if let Some(path) = instruction.downcast_extern::<bindings::Path>() {
+            draw_path(pixmap, path);
+        } else if let Some(graph) = instruction.downcast_extern::<bindings::Graph>() {
+            draw_graph(pixmap, graph);
+        } else if let Some(bar) = instruction.downcast_extern::<bindings::Bar>() {
+            draw_bar(pixmap, bar);
+        } else {
+            eprintln!("unrecognized drawing instruction: {instruction:?}");
+        }
+    }
+
+    fn label(&mut self, pixmap: &mut Pixmap, label: &bindings::Label) {
+        let (width, height) = (pixmap.width(), pixmap.height());
+        let mut buffer = Buffer::new(
+            &mut self.font_system,
+            Metrics {
+                font_size: 14.0,
+                line_height: height as f32,
+            },
+        );
+        let mut buffer = buffer.borrow_with(&mut self.font_system);
+        buffer.set_size(Some(width as f32), Some(height as f32));
+        buffer.shape_until_scroll(true);
+        buffer.set_text(&label.text, &Attrs::new(), cosmic_text::Shaping::Advanced);
+        buffer.draw(
+            &mut self.swash_cache,
+            Color::rgba(label.red, label.green, label.blue, label.alpha),
+            |x, y, w, h, color| {
+                for y in y..(y + h as i32) {
+                    for x in x..(x + w as i32) {
+                        blend(pixmap, x, y, color);
+                    }
+                }
+            },
+        );
+    }
+
+    fn image(&mut self, pixmap: &mut Pixmap, image: &bindings::Image) {
+        let y = image
+            .y
+            .unwrap_or((pixmap.height() as i32 - image.size as i32) / 2);
+        let path = match &image.source {
+            bindings::ImageSource::Path(path) => Path::new(&**path),
+            bindings::ImageSource::Icon(name) => match self.icons.find(name, image.size) {
+                Some(path) => path,
+                None => {
+                    eprintln!("icon {name} not found");
+                    return;
+                }
+            },
+        };
+        match self.images.get(path, image.size) {
+            Ok(source) => pixmap.draw_pixmap(
+                image.x,
+                y,
+                source.as_ref(),
+                &PixmapPaint::default(),
+                Transform::identity(),
+                None,
+            ),
+            Err(e) => eprintln!("failed to load image {}: {e}", path.display()),
+        }
+    }
+}
+
+fn draw_path(pixmap: &mut Pixmap, path: &bindings::Path) {
+    let mut builder = PathBuilder::new();
+    for segment in &path.segments {
+        match *segment {
+            bindings::PathSegment::MoveTo { x, y } => builder.move_to(x as f32, y as f32),
+            bindings::PathSegment::LineTo { x, y } => builder.line_to(x as f32, y as f32),
+            bindings::PathSegment::Arc {
+                x,
+                y,
+                radius,
+                start,
+                end,
+            } => arc(
+                &mut builder,
+                x as f32,
+                y as f32,
+                radius as f32,
+                (start as f32).to_radians(),
+                (end as f32).to_radians(),
+            ),
+            bindings::PathSegment::Close => builder.close(),
+        }
+    }
+    let Some(shape) = builder.finish() else {
+        return;
+    };
+    let paint = paint(path.color);
+    match path.paint {
+        bindings::PathPaint::Stroke { width } => pixmap.stroke_path(
+            &shape,
+            &paint,
+            &Stroke {
+                width: width as f32,
+                ..Default::default()
+            },
+            Transform::identity(),
+            None,
+        ),
+        bindings::PathPaint::Fill => pixmap.fill_path(
+            &shape,
+            &paint,
+            FillRule::Winding,
+            Transform::identity(),
+            None,
+        ),
+    }
+}
+
+fn draw_graph(pixmap: &mut Pixmap, graph: &bindings::Graph) {
+    let (width, height) = (pixmap.width() as f32, pixmap.height() as f32);
+    if let Some(background) = graph.background
+        && let Some(rect) = Rect::from_xywh(0.0, 0.0, width, height)
+    {
+        pixmap.fill_rect(rect, &paint(background), Transform::identity(), None);
+    }
+    let max = graph
+        .max
+        .unwrap_or_else(|| graph.samples.iter().copied().max().unwrap_or(graph.min))
+        .max(graph.min + 1);
+    let range = (max - graph.min) as f32;
+    // Samples are right-aligned, so the graph scrolls left as the history fills up.
+    let spacing = width / graph.capacity.max(1) as f32;
+    let offset = graph.capacity.saturating_sub(graph.samples.len()) as f32;
+    let points = graph.samples.iter().enumerate().map(|(i, sample)| {
+        let x = (offset + i as f32 + 0.5) * spacing;
+        let y = height - (*sample - graph.min) as f32 / range * height;
+        (x, y.clamp(0.0, height))
+    });
+    let paint = paint(graph.color);
+    match graph.style {
+        bindings::GraphStyle::Line | bindings::GraphStyle::Area => {
+            let mut builder = PathBuilder::new();
+            for (x, y) in points {
+                if builder.is_empty() {
+                    builder.move_to(x, y);
+                } else {
+                    builder.line_to(x, y);
+                }
+            }
+            if let bindings::GraphStyle::Area = graph.style
+                && let Some(last) = builder.last_point()
+            {
+                builder.line_to(last.x, height);
+                builder.line_to((offset + 0.5) * spacing, height);
+                builder.close();
+            }
+            let Some(shape) = builder.finish() else {
+                return;
+            };
+            if let bindings::GraphStyle::Area = graph.style {
+                pixmap.fill_path(
+                    &shape,
+                    &paint,
+                    FillRule::Winding,
+                    Transform::identity(),
+                    None,
+                );
+            } else {
+                pixmap.stroke_path(
+                    &shape,
+                    &paint,
+                    &Stroke::default(),
+                    Transform::identity(),
+                    None,
+                );
+            }
+        }
+        bindings::GraphStyle::Bar => {
+            // Leave a gap between bars once they're wide enough for it to be visible.
+            let gap = if spacing >= 3.0 { 1.0 } else { 0.0 };
+            for (x, y) in points {
+                if let Some(rect) = Rect::from_xywh(x - spacing / 2.0, y, spacing - gap, height - y)
+                {
+                    pixmap.fill_rect(rect, &paint, Transform::identity(), None);
+                }
+            }
+        }
+    }
+}
+
+fn draw_bar(pixmap: &mut Pixmap, bar: &bindings::Bar) {
+    let (x, y) = (bar.x as f32, bar.y as f32);
+    let width = bar
+        .width
+        .map_or(pixmap.width() as f32 - x, |width| width as f32);
+    let height = bar
+        .height
+        .map_or(pixmap.height() as f32 - y, |height| height as f32);
+    if let Some(background) = bar.background
+        && let Some(rect) = Rect::from_xywh(x, y, width, height)
+    {
+        pixmap.fill_rect(rect, &paint(background), Transform::identity(), None);
+    }
+    let range = (bar.max - bar.min).max(1) as f32;
+    let fraction = ((bar.value - bar.min) as f32 / range).clamp(0.0, 1.0);
+    let rect = match bar.direction {
+        bindings::BarDirection::Horizontal => Rect::from_xywh(x, y, width * fraction, height),
+        bindings::BarDirection::Vertical => {
+            Rect::from_xywh(x, y + height * (1.0 - fraction), width, height * fraction)
+        }
+    };
+    // An empty bar has no valid rect.
+    if let Some(rect) = rect {
+        pixmap.fill_rect(rect, &paint(bar.fill()), Transform::identity(), None);
+    }
+}
+
+fn paint(color: [u8; 4]) -> Paint<'static> {
+    let [red, green, blue, alpha] = color;
+    let mut paint = Paint::default();
+    paint.set_color_rgba8(red, green, blue, alpha);
+    paint.anti_alias = true;
+    paint
+}
+
+/// Appends a circular arc to the path, approximated by one cubic curve per quarter turn.
+///
+/// Like the HTML canvas API, the arc is connected to the previous segment with a straight line.
+fn arc(builder: &mut PathBuilder, x: f32, y: f32, radius: f32, start: f32, end: f32) {
+    let point = |angle: f32| (x + radius * angle.cos(), y + radius * angle.sin());
+    let (start_x, start_y) = point(start);
+    if builder.is_empty() {
+        builder.move_to(start_x, start_y);
+    } else {
+        builder.line_to(start_x, start_y);
+    }
+    let segments = ((end - start).abs() / FRAC_PI_2).ceil().max(1.0);
+    let step = (end - start) / segments;
+    // Distance of each control point from its endpoint, along the tangent.
+    let handle = radius * 4.0 / 3.0 * (step / 4.0).tan();
+    for i in 0..segments as u32 {
+        let from = start + step * i as f32;
+        let to = from + step;
+        let (from_x, from_y) = point(from);
+        let (to_x, to_y) = point(to);
+        builder.cubic_to(
+            from_x - handle * from.sin(),
+            from_y + handle * from.cos(),
+            to_x + handle * to.sin(),
+            to_y - handle * to.cos(),
+            to_x,
+            to_y,
+        );
+    }
+}
+
+/// Composites a single unpremultiplied pixel over the pixmap.
+fn blend(pixmap: &mut Pixmap, x: i32, y: i32, color: Color) {
+    let (Ok(x), Ok(y)) = (u32::try_from(x), u32::try_from(y)) else {
+        return;
+    };
+    if color.a() == 0 || x >= pixmap.width() || y >= pixmap.height() {
+        return;
+    }
+    let width = pixmap.width();
+    let dest = &mut pixmap.pixels_mut()[(x + y * width) as usize];
+    let a = color.a() as u16;
+    let over =
+        |source: u8, dest: u8| (source as u16 * a / 255 + dest as u16 * (255 - a) / 255) as u8;
+    if let Some(color) = PremultipliedColorU8::from_rgba(
+        over(color.r(), dest.red()),
+        over(color.g(), dest.green()),
+        over(color.b(), dest.blue()),
+        over(255, dest.alpha()),
+    ) {
+        *dest = color;
+    }
+}
+
+/// Converts tiny-skia's premultiplied RGBA to wl_shm's little-endian ARGB.
+pub fn to_argb8888(pixmap: &Pixmap) -> Box<[u8]> {
+    pixmap
+        .data()
+        .chunks_exact(4)
+        .flat_map(|rgba| [rgba[2], rgba[1], rgba[0], rgba[3]])
+        .collect()
+}
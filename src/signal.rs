@@ -0,0 +1,118 @@
+//! Redrawing widgets when psybeam receives real-time signals, like i3blocks:
+//! `pkill -RTMIN+5 psybeam` redraws every widget created with `signal: 5`.
+//!
+//! Signals are blocked and read from a signalfd, so they never interrupt drawing.
+//! Commands don't inherit the blocked signals, as the standard library resets the signal mask
+//! of every child it spawns.
+
+use crate::{WidgetState, event};
+use rustix::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::cell::RefCell;
+use std::io;
+use std::mem::{self, MaybeUninit};
+use std::rc::Rc;
+
+struct Signals {
+    fd: OwnedFd,
+    mask: libc::sigset_t,
+    /// Widgets alongside the signal that redraws them.
+    owners: Vec<(i32, Rc<WidgetState>)>,
+}
+
+thread_local! {
+    static SIGNALS: RefCell<Option<Signals>> = const { RefCell::new(None) };
+}
+
+/// Marks `owner` for redrawing whenever psybeam receives `SIGRTMIN+offset`.
+pub fn watch(owner: &Rc<WidgetState>, offset: i64) -> io::Result<()> {
+    let signal = i32::try_from(offset)
+        .ok()
+        .map(|offset| libc::SIGRTMIN() + offset)
+        .filter(|signal| (libc::SIGRTMIN()..=libc::SIGRTMAX()).contains(signal))
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "signal {offset} is out of range (0 to {})",
+                    libc::SIGRTMAX() - libc::SIGRTMIN()
+                ),
+            )
+        })?;
+    SIGNALS.with_borrow_mut(|signals| {
+        let mut mask = match signals {
+            Some(signals) => signals.mask,
+            None => {
+                let mut mask = MaybeUninit::uninit();
+                // SAFETY: sigemptyset initializes the set.
+                unsafe {
+                    libc::sigemptyset(mask.as_mut_ptr());
+                    mask.assume_init()
+                }
+            }
+        };
+        // SAFETY: the set was initialized above, and psybeam has no other threads to race with.
+        unsafe {
+            libc::sigaddset(&mut mask, signal);
+            if libc::pthread_sigmask(libc::SIG_BLOCK, &mask, std::ptr::null_mut()) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        // Passing an existing signalfd replaces its mask.
+        let existing = signals
+            .as_ref()
+            .map_or(-1, |signals| signals.fd.as_raw_fd());
+        // SAFETY: the mask is initialized, and `existing` is either -1 or a signalfd.
+        let fd = unsafe { libc::signalfd(existing, &mask, libc::SFD_NONBLOCK | libc::SFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let signals = match signals {
+            Some(signals) => signals,
+            None => {
+                // SAFETY: signalfd returned a new descriptor, which nothing else owns.
+                let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+                event::register(&fd)?;
+                signals.insert(Signals {
+                    fd,
+                    mask,
+                    owners: Vec::new(),
+                })
+            }
+        };
+        signals.mask = mask;
+        signals.owners.push((signal, owner.clone()));
+        Ok(())
+    })
+}
+
+/// Reads pending signals, marking the widgets they concern for redrawing.
+pub fn update() {
+    SIGNALS.with_borrow(|signals| {
+        let Some(signals) = signals else {
+            return;
+        };
+        let mut info = [0; mem::size_of::<libc::signalfd_siginfo>()];
+        loop {
+            match rustix::io::read(&signals.fd, &mut info) {
+                Ok(length) if length == info.len() => {
+                    // `ssi_signo` is the first field of signalfd_siginfo.
+                    let signal = u32::from_ne_bytes([info[0], info[1], info[2], info[3]]) as i32;
+                    for (_, owner) in signals
+                        .owners
+                        .iter()
+                        .filter(|(owner_signal, _)| *owner_signal == signal)
+                    {
+                        owner.dirty.set(true);
+                    }
+                }
+                Ok(_) => return,
+                Err(rustix::io::Errno::INTR) => {}
+                Err(rustix::io::Errno::AGAIN) => return,
+                Err(e) => {
+                    eprintln!("failed to read signals: {e}");
+                    return;
+                }
+            }
+        }
+    });
+}
@@ -0,0 +1,489 @@
+//! Readers for the system statistics exposed through procfs and sysfs, for `psybeam.sys`.
+//!
+//! The roots of both filesystems can be moved with `PSYBEAM_PROCFS` and `PSYBEAM_SYSFS`,
+//! so that scripts can be tried against fixture files.
+//! Parsing is kept apart from reading, taking either a file's text or the directory to read,
+//! so that it can be tested against the same fixtures.
+
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{env, fs, io};
+
+fn root(variable: &str, default: &str, path: impl AsRef<Path>) -> PathBuf {
+    env::var_os(variable)
+        .map_or_else(|| PathBuf::from(default), PathBuf::from)
+        .join(path)
+}
+
+/// Resolves a path within procfs, such as "stat".
+pub fn procfs(path: impl AsRef<Path>) -> PathBuf {
+    root("PSYBEAM_PROCFS", "/proc", path)
+}
+
+/// Resolves a path within sysfs, such as "class/power_supply".
+pub fn sysfs(path: impl AsRef<Path>) -> PathBuf {
+    root("PSYBEAM_SYSFS", "/sys", path)
+}
+
+/// Resolves a path within the repository's fixtures, such as "proc/stat".
+#[cfg(test)]
+pub fn fixture(path: impl AsRef<Path>) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fixtures")
+        .join(path)
+}
+
+fn invalid(what: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, format!("malformed {what}"))
+}
+
+/// Time spent in each state by a CPU since boot, in clock ticks.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct CpuTimes {
+    pub user: i64,
+    pub nice: i64,
+    pub system: i64,
+    pub idle: i64,
+    pub iowait: i64,
+    pub irq: i64,
+    pub softirq: i64,
+    pub steal: i64,
+}
+
+impl CpuTimes {
+    /// Time spent waiting for work, including waiting on IO.
+    pub fn idle(&self) -> i64 {
+        self.idle + self.iowait
+    }
+
+    pub fn total(&self) -> i64 {
+        self.user
+            + self.nice
+            + self.system
+            + self.idle
+            + self.iowait
+            + self.irq
+            + self.softirq
+            + self.steal
+    }
+}
+
+#[derive(Debug)]
+pub struct Cpu {
+    pub times: CpuTimes,
+    /// The percentage of time spent busy since boot, or since the times given to
+    /// [`Cpus::measure`].
+    pub usage: i64,
+}
+
+impl Cpu {
+    fn new(times: CpuTimes) -> Self {
+        let mut cpu = Self { times, usage: 0 };
+        cpu.measure(CpuTimes::default());
+        cpu
+    }
+
+    fn measure(&mut self, last: CpuTimes) {
+        let total = self.times.total() - last.total();
+        let idle = self.times.idle() - last.idle();
+        self.usage = if total > 0 {
+            (total - idle) * 100 / total
+        } else {
+            0
+        };
+    }
+}
+
+#[derive(Debug)]
+pub struct Cpus {
+    pub total: Cpu,
+    /// Each core, in the order they are listed in /proc/stat.
+    pub cores: Vec<Cpu>,
+}
+
+impl Cpus {
+    /// The times of every CPU, total first.
+    pub fn times(&self) -> Vec<CpuTimes> {
+        std::iter::once(&self.total)
+            .chain(&self.cores)
+            .map(|cpu| cpu.times)
+            .collect()
+    }
+
+    /// Measures usage since `last`, as returned by [`Cpus::times`].
+    pub fn measure(&mut self, last: &[CpuTimes]) {
+        for (cpu, last) in std::iter::once(&mut self.total)
+            .chain(&mut self.cores)
+            .zip(last)
+        {
+            cpu.measure(*last);
+        }
+    }
+}
+
+/// Parses the `cpu` lines of /proc/stat, total first.
+fn parse_stat(stat: &str) -> io::Result<Vec<CpuTimes>> {
+    stat.lines()
+        .filter(|line| line.starts_with("cpu"))
+        .map(|line| {
+            let mut fields = line
+                .split_whitespace()
+                .skip(1)
+                .map(|field| field.parse::<i64>().map_err(|_| invalid("/proc/stat")));
+            let mut next = || fields.next().unwrap_or(Ok(0));
+            Ok(CpuTimes {
+                user: next()?,
+                nice: next()?,
+                system: next()?,
+                idle: next()?,
+                iowait: next()?,
+                irq: next()?,
+                softirq: next()?,
+                steal: next()?,
+            })
+        })
+        .collect()
+}
+
+fn parse_cpus(stat: &str) -> io::Result<Cpus> {
+    let mut cpus = parse_stat(stat)?.into_iter().map(Cpu::new);
+    Ok(Cpus {
+        total: cpus.next().ok_or_else(|| invalid("/proc/stat"))?,
+        cores: cpus.collect(),
+    })
+}
+
+pub fn cpu() -> io::Result<Cpus> {
+    parse_cpus(&fs::read_to_string(procfs("stat"))?)
+}
+
+/// Memory statistics from /proc/meminfo, in bytes.
+#[derive(Debug, Default)]
+pub struct Memory {
+    pub total: i64,
+    pub free: i64,
+    /// An estimate of how much memory can be allocated without swapping.
+    pub available: i64,
+    pub buffers: i64,
+    pub cached: i64,
+    pub swap_total: i64,
+    pub swap_free: i64,
+}
+
+fn parse_meminfo(meminfo: &str) -> io::Result<Memory> {
+    let mut memory = Memory::default();
+    for line in meminfo.lines() {
+        let Some((key, value)) = line.split_once(':') else {
+            continue;
+        };
+        let field = match key {
+            "MemTotal" => &mut memory.total,
+            "MemFree" => &mut memory.free,
+            "MemAvailable" => &mut memory.available,
+            "Buffers" => &mut memory.buffers,
+            "Cached" => &mut memory.cached,
+            "SwapTotal" => &mut memory.swap_total,
+            "SwapFree" => &mut memory.swap_free,
+            _ => continue,
+        };
+        // Values are given in KiB, despite the "kB" suffix.
+        let kibibytes = value
+            .trim()
+            .trim_end_matches("kB")
+            .trim_end()
+            .parse::<i64>()
+            .map_err(|_| invalid("/proc/meminfo"))?;
+        *field = kibibytes * 1024;
+    }
+    Ok(memory)
+}
+
+pub fn memory() -> io::Result<Memory> {
+    parse_meminfo(&fs::read_to_string(procfs("meminfo"))?)
+}
+
+/// Load averages, in hundredths.
+#[derive(Debug)]
+pub struct Load {
+    pub one: i64,
+    pub five: i64,
+    pub fifteen: i64,
+    /// Threads currently running.
+    pub running: i64,
+    pub threads: i64,
+}
+
+/// Parses a non-negative decimal such as "1.05" into hundredths.
+fn hundredths(decimal: &str) -> Option<i64> {
+    let (whole, fraction) = decimal.split_once('.').unwrap_or((decimal, ""));
+    let fraction = format!("{fraction:0<2}");
+    Some(whole.parse::<i64>().ok()? * 100 + fraction.get(..2)?.parse::<i64>().ok()?)
+}
+
+fn parse_loadavg(loadavg: &str) -> io::Result<Load> {
+    let mut fields = loadavg.split_whitespace();
+    let mut load = || {
+        fields
+            .next()
+            .and_then(hundredths)
+            .ok_or_else(|| invalid("/proc/loadavg"))
+    };
+    let (one, five, fifteen) = (load()?, load()?, load()?);
+    let (running, threads) = fields
+        .next()
+        .and_then(|tasks| tasks.split_once('/'))
+        .and_then(|(running, threads)| Some((running.parse().ok()?, threads.parse().ok()?)))
+        .ok_or_else(|| invalid("/proc/loadavg"))?;
+    Ok(Load {
+        one,
+        five,
+        fifteen,
+        running,
+        threads,
+    })
+}
+
+pub fn load() -> io::Result<Load> {
+    parse_loadavg(&fs::read_to_string(procfs("loadavg"))?)
+}
+
+fn parse_uptime(uptime: &str) -> io::Result<i64> {
+    uptime
+        .split_whitespace()
+        .next()
+        .and_then(|uptime| uptime.split('.').next()?.parse().ok())
+        .ok_or_else(|| invalid("/proc/uptime"))
+}
+
+/// Seconds since boot.
+pub fn uptime() -> io::Result<i64> {
+    parse_uptime(&fs::read_to_string(procfs("uptime"))?)
+}
+
+/// A temperature sensor, in millidegrees Celsius.
+#[derive(Debug)]
+pub struct Temperature {
+    /// The sensor's label, such as "Package id 0", or its file prefix, such as "temp1".
+    pub label: Rc<str>,
+    pub temperature: i64,
+    pub max: Option<i64>,
+    pub critical: Option<i64>,
+}
+
+#[derive(Debug)]
+pub struct Fan {
+    /// The fan's label, or its file prefix, such as "fan1".
+    pub label: Rc<str>,
+    pub rpm: i64,
+}
+
+/// A hardware monitoring chip, such as "coretemp" or "nvme".
+#[derive(Debug)]
+pub struct Chip {
+    pub name: Rc<str>,
+    pub temperatures: Vec<Temperature>,
+    pub fans: Vec<Fan>,
+}
+
+/// Reads the channels of one kind, such as "temp", in order of their index.
+fn channels<T>(
+    path: &Path,
+    files: &[String],
+    kind: &str,
+    read: impl Fn(Rc<str>, &dyn Fn(&str) -> Option<i64>) -> Option<T>,
+) -> Vec<T> {
+    let mut indices = files
+        .iter()
+        .filter_map(|file| {
+            file.strip_prefix(kind)?
+                .strip_suffix("_input")?
+                .parse::<u32>()
+                .ok()
+        })
+        .collect::<Vec<_>>();
+    indices.sort_unstable();
+    indices
+        .into_iter()
+        .filter_map(|index| {
+            let prefix = format!("{kind}{index}");
+            let value = |suffix: &str| {
+                fs::read_to_string(path.join(format!("{prefix}_{suffix}")))
+                    .ok()?
+                    .trim()
+                    .parse()
+                    .ok()
+            };
+            let label = fs::read_to_string(path.join(format!("{prefix}_label")))
+                .map_or_else(|_| prefix.as_str().into(), |label| label.trim().into());
+            read(label, &value)
+        })
+        .collect()
+}
+
+/// Every chip in a directory laid out like /sys/class/hwmon.
+///
+/// The numbering of hwmon devices depends on the order their drivers were loaded in,
+/// which changes across boots, so chips are sorted by name rather than by number.
+///
+/// Chips which can't be read are skipped rather than failing the whole scan.
+fn read_sensors(hwmon: &Path) -> io::Result<Vec<Chip>> {
+    let mut chips = Vec::new();
+    for entry in fs::read_dir(hwmon)?.flatten() {
+        let mut path = entry.path();
+        // Some older drivers keep their attributes on the parent device.
+        if !path.join("name").exists() {
+            path.push("device");
+        }
+        let Ok(name) = fs::read_to_string(path.join("name")) else {
+            continue;
+        };
+        let Ok(files) = fs::read_dir(&path) else {
+            continue;
+        };
+        let files = files
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect::<Vec<_>>();
+        chips.push(Chip {
+            name: name.trim().into(),
+            // Some sensors report an error while their device is asleep, and are skipped.
+            temperatures: channels(&path, &files, "temp", |label, value| {
+                Some(Temperature {
+                    label,
+                    temperature: value("input")?,
+                    max: value("max"),
+                    critical: value("crit"),
+                })
+            }),
+            fans: channels(&path, &files, "fan", |label, value| {
+                Some(Fan {
+                    label,
+                    rpm: value("input")?,
+                })
+            }),
+        });
+    }
+    chips.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(chips)
+}
+
+/// Every chip in /sys/class/hwmon, sorted by name.
+pub fn sensors() -> io::Result<Vec<Chip>> {
+    read_sensors(&sysfs("class/hwmon"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cpu_usage_since_boot() {
+        let cpus = parse_cpus(include_str!("../fixtures/proc/stat")).unwrap();
+        assert_eq!(cpus.total.times.total(), 1000);
+        assert_eq!(cpus.total.times.idle(), 850);
+        assert_eq!(cpus.total.usage, 15);
+        assert_eq!(
+            cpus.cores.iter().map(|cpu| cpu.usage).collect::<Vec<_>>(),
+            [16, 14]
+        );
+    }
+
+    #[test]
+    fn cpu_usage_between_samples() {
+        let last = parse_cpus(include_str!("../fixtures/proc/stat"))
+            .unwrap()
+            .times();
+        let mut cpus = parse_cpus(include_str!("../fixtures/proc/stat-later")).unwrap();
+        cpus.measure(&last);
+        assert_eq!(cpus.total.usage, 40);
+        assert_eq!(
+            cpus.cores.iter().map(|cpu| cpu.usage).collect::<Vec<_>>(),
+            [50, 30]
+        );
+        // No time passing isn't a division by zero.
+        cpus.measure(&cpus.times());
+        assert_eq!(cpus.total.usage, 0);
+    }
+
+    #[test]
+    fn stat_from_older_kernels() {
+        // Kernels before 2.6.11 didn't report steal time.
+        let times = parse_stat("cpu  1 2 3 4 5 6 7\n").unwrap();
+        assert_eq!(times[0].softirq, 7);
+        assert_eq!(times[0].steal, 0);
+        assert!(parse_stat("cpu  1 x 3\n").is_err());
+        assert!(parse_cpus("intr 1 2 3\n").is_err());
+    }
+
+    #[test]
+    fn meminfo() {
+        let memory = parse_meminfo(include_str!("../fixtures/proc/meminfo")).unwrap();
+        assert_eq!(memory.total, 16318480 * 1024);
+        assert_eq!(memory.free, 1234567 * 1024);
+        assert_eq!(memory.available, 8765432 * 1024);
+        assert_eq!(memory.buffers, 123456 * 1024);
+        assert_eq!(memory.cached, 4567890 * 1024);
+        assert_eq!(memory.swap_total, 8388604 * 1024);
+        assert_eq!(memory.swap_free, 8388000 * 1024);
+        assert!(parse_meminfo("MemTotal: lots kB\n").is_err());
+    }
+
+    #[test]
+    fn loadavg() {
+        let load = parse_loadavg(include_str!("../fixtures/proc/loadavg")).unwrap();
+        assert_eq!((load.one, load.five, load.fifteen), (52, 105, 90));
+        assert_eq!((load.running, load.threads), (2, 1234));
+        assert!(parse_loadavg("0.52 1.05\n").is_err());
+    }
+
+    #[test]
+    fn uptime() {
+        assert_eq!(
+            parse_uptime(include_str!("../fixtures/proc/uptime")).unwrap(),
+            12345
+        );
+        assert!(parse_uptime("").is_err());
+    }
+
+    #[test]
+    fn sensors() {
+        let chips = read_sensors(&fixture("sys/class/hwmon")).unwrap();
+        // The chip without a name, and the link to a device which has gone away, are skipped.
+        assert_eq!(
+            chips.iter().map(|chip| &*chip.name).collect::<Vec<_>>(),
+            ["coretemp", "nvme", "thinkpad"]
+        );
+
+        let temperatures = &chips[0].temperatures;
+        assert_eq!(
+            temperatures
+                .iter()
+                .map(|sensor| (&*sensor.label, sensor.temperature))
+                .collect::<Vec<_>>(),
+            [
+                ("Package id 0", 45000),
+                ("Core 0", 43000),
+                ("temp10", 44000)
+            ]
+        );
+        assert_eq!(temperatures[0].critical, Some(100000));
+        assert_eq!(temperatures[0].max, None);
+
+        // The sensor which can't be read is skipped.
+        let nvme = &chips[1].temperatures;
+        assert_eq!(nvme.len(), 1);
+        assert_eq!(nvme[0].max, Some(81850));
+        assert_eq!(nvme[0].critical, Some(84850));
+
+        // Attributes on the parent device are found.
+        let thinkpad = &chips[2];
+        assert_eq!(thinkpad.temperatures[0].temperature, 52000);
+        assert_eq!(
+            thinkpad
+                .fans
+                .iter()
+                .map(|fan| (&*fan.label, fan.rpm))
+                .collect::<Vec<_>>(),
+            [("fan1", 2100), ("fan2", 0)]
+        );
+    }
+}
@@ -0,0 +1,131 @@
+//! Wall-clock aligned timers, for `psybeam.widget.refresh.aligned`.
+//!
+//! Widgets are redrawn on multiples of their period since the Unix epoch,
+//! so a clock refreshed every minute changes as soon as the minute does.
+//! Periods are aligned in UTC, so those longer than an hour may not line up with local time.
+//!
+//! A single realtime timerfd is armed for the earliest boundary.
+//! Unlike `Instant`s, it keeps counting during suspend,
+//! and `TFD_TIMER_CANCEL_ON_SET` reports when the clock is set so that every timer can re-align.
+
+use crate::{WidgetState, event};
+use rustix::fd::OwnedFd;
+use rustix::io::Errno;
+use rustix::time::{Itimerspec, TimerfdClockId, TimerfdFlags, TimerfdTimerFlags, Timespec};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, SystemTime};
+
+struct Timer {
+    owner: Rc<WidgetState>,
+    period: Duration,
+    /// The next boundary, as time since the Unix epoch.
+    next: Duration,
+}
+
+thread_local! {
+    static TIMERFD: OwnedFd = {
+        let timerfd = rustix::time::timerfd_create(
+            TimerfdClockId::Realtime,
+            TimerfdFlags::CLOEXEC | TimerfdFlags::NONBLOCK,
+        )
+        .expect("failed to create timerfd");
+        event::register(&timerfd).expect("failed to register timerfd");
+        timerfd
+    };
+    static TIMERS: RefCell<Vec<Timer>> = RefCell::default();
+}
+
+fn now() -> Duration {
+    SystemTime::now()
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// The first multiple of `period` after `now`.
+fn boundary(now: Duration, period: Duration) -> Duration {
+    let period = period.as_nanos();
+    let next = (now.as_nanos() / period + 1) * period;
+    Duration::new((next / 1_000_000_000) as u64, (next % 1_000_000_000) as u32)
+}
+
+/// Arms the timerfd for the earliest boundary,
+/// re-aligning every timer if the clock was set in the meantime.
+fn arm(timers: &mut [Timer]) {
+    loop {
+        let Some(next) = timers.iter().map(|timer| timer.next).min() else {
+            return;
+        };
+        let Ok(next) = Timespec::try_from(next) else {
+            return;
+        };
+        let result = TIMERFD.with(|timerfd| {
+            rustix::time::timerfd_settime(
+                timerfd,
+                TimerfdTimerFlags::ABSTIME | TimerfdTimerFlags::CANCEL_ON_SET,
+                &Itimerspec {
+                    it_interval: Timespec {
+                        tv_sec: 0,
+                        tv_nsec: 0,
+                    },
+                    it_value: next,
+                },
+            )
+        });
+        match result {
+            Ok(_) => return,
+            Err(Errno::CANCELED) => realign(timers),
+            Err(e) => {
+                eprintln!("failed to arm timerfd: {e}");
+                return;
+            }
+        }
+    }
+}
+
+fn realign(timers: &mut [Timer]) {
+    let now = now();
+    for timer in timers {
+        timer.owner.dirty.set(true);
+        timer.next = boundary(now, timer.period);
+    }
+}
+
+/// Marks `owner` for redrawing on every multiple of `period`.
+pub fn align(owner: &Rc<WidgetState>, period: Duration) {
+    TIMERS.with_borrow_mut(|timers| {
+        timers.push(Timer {
+            owner: owner.clone(),
+            period,
+            next: boundary(now(), period),
+        });
+        arm(timers);
+    });
+}
+
+/// Marks widgets whose boundary has passed for redrawing, and re-arms the timer.
+pub fn update() {
+    TIMERS.with_borrow_mut(|timers| {
+        if timers.is_empty() {
+            return;
+        }
+        let mut expirations = [0; 8];
+        match TIMERFD.with(|timerfd| rustix::io::read(timerfd, &mut expirations)) {
+            Ok(_) => {
+                let now = now();
+                for timer in timers.iter_mut().filter(|timer| timer.next <= now) {
+                    timer.owner.dirty.set(true);
+                    timer.next = boundary(now, timer.period);
+                }
+            }
+            // The clock was set, so any boundary could be wrong.
+            Err(Errno::CANCELED) => realign(timers),
+            Err(Errno::AGAIN) => return,
+            Err(e) => {
+                eprintln!("failed to read timerfd: {e}");
+                return;
+            }
+        }
+        arm(timers);
+    });
+}
@@ -0,0 +1,106 @@
+//! Redrawing widgets when files change, for `psybeam.widget.refresh.watch`.
+//!
+//! Many files in sysfs never report changes through inotify;
+//! widgets watching them should also be given a fallback timer.
+
+use crate::{WidgetState, event};
+use rustix::fd::OwnedFd;
+use rustix::fs::inotify::{self, ReadFlags, WatchFlags};
+use rustix::io::Errno;
+use std::cell::RefCell;
+use std::mem::MaybeUninit;
+use std::rc::Rc;
+
+thread_local! {
+    static INOTIFY: OwnedFd = {
+        let inotify = inotify::init(inotify::CreateFlags::CLOEXEC | inotify::CreateFlags::NONBLOCK)
+            .expect("failed to create inotify instance");
+        event::register(&inotify).expect("failed to register inotify instance");
+        inotify
+    };
+    static WATCHES: RefCell<Vec<Watch>> = RefCell::default();
+}
+
+struct Watch {
+    owner: Rc<WidgetState>,
+    path: Rc<str>,
+    /// `None` while the path doesn't exist.
+    descriptor: Option<i32>,
+}
+
+impl Watch {
+    /// Starts watching the path if it isn't being watched already,
+    /// returning whether it was added.
+    fn add(&mut self) -> bool {
+        if self.descriptor.is_some() {
+            return false;
+        }
+        // Directories report changes to their entries, and files to their contents.
+        let flags = WatchFlags::MODIFY
+            | WatchFlags::ATTRIB
+            | WatchFlags::CLOSE_WRITE
+            | WatchFlags::CREATE
+            | WatchFlags::DELETE
+            | WatchFlags::MOVE
+            | WatchFlags::DELETE_SELF
+            | WatchFlags::MOVE_SELF;
+        self.descriptor = INOTIFY
+            .with(|inotify| inotify::add_watch(inotify, &*self.path, flags))
+            .ok();
+        self.descriptor.is_some()
+    }
+}
+
+/// Marks `owner` for redrawing whenever the file or directory at `path` changes.
+pub fn watch(owner: &Rc<WidgetState>, path: Rc<str>) {
+    let mut watch = Watch {
+        owner: owner.clone(),
+        path,
+        descriptor: None,
+    };
+    if !watch.add() {
+        eprintln!("can't watch {} yet, as it doesn't exist", watch.path);
+    }
+    WATCHES.with_borrow_mut(|watches| watches.push(watch));
+}
+
+/// Reads pending inotify events, marking the widgets they concern for redrawing.
+pub fn update() {
+    WATCHES.with_borrow_mut(|watches| {
+        if watches.is_empty() {
+            return;
+        }
+        INOTIFY.with(|inotify| {
+            let mut buffer = [MaybeUninit::uninit(); 4096];
+            let mut reader = inotify::Reader::new(inotify, &mut buffer);
+            loop {
+                let event = match reader.next() {
+                    Ok(event) => event,
+                    Err(Errno::INTR) => continue,
+                    Err(Errno::AGAIN) => break,
+                    Err(e) => {
+                        eprintln!("failed to read inotify events: {e}");
+                        break;
+                    }
+                };
+                // Events were lost, so anything could have changed.
+                let overflowed = event.events().contains(ReadFlags::QUEUE_OVERFLOW);
+                for watch in watches.iter_mut() {
+                    if overflowed || watch.descriptor == Some(event.wd()) {
+                        watch.owner.dirty.set(true);
+                        // The path was deleted or moved (or its filesystem unmounted).
+                        if event.events().contains(ReadFlags::IGNORED) {
+                            watch.descriptor = None;
+                        }
+                    }
+                }
+            }
+        });
+        // Paths that have been replaced (or created) can only be picked up by trying again.
+        for watch in watches.iter_mut() {
+            if watch.add() {
+                watch.owner.dirty.set(true);
+            }
+        }
+    });
+}
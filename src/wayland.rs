@@ -1,8 +1,8 @@
-use crate::{Psybeam, Widget, bindings};
-use cosmic_text::{Attrs, Buffer, Color, Metrics};
+use crate::{Psybeam, Widget, render};
 use std::io::Write;
-use std::iter;
 use std::os::unix::io::AsFd;
+use std::time::Instant;
+use tiny_skia::{Pixmap, PixmapPaint, Transform};
 use wayland_client::protocol::{
     wl_buffer, wl_callback, wl_compositor, wl_output, wl_registry, wl_shm, wl_shm_pool, wl_surface,
 };
@@ -58,6 +58,8 @@ pub struct PsybeamFinal {
     width: u32,
 
     layer_surface: ZwlrLayerSurfaceV1,
+    /// Buffers may not be attached until the layer surface's first configure event.
+    configured: bool,
 }
 
 pub enum PsybeamResources {
@@ -80,24 +82,54 @@ impl Psybeam {
         final_resources
     }
 
+    /// Draws a new frame if any widget is due and the compositor is ready for one.
+    pub fn redraw(&mut self, qh: &QueueHandle<Self>) {
+        let PsybeamResources::Final(PsybeamFinal {
+            configured: true, ..
+        }) = &self.resources
+        else {
+            return;
+        };
+        let now = Instant::now();
+        if self.frame_pending
+            || !self.needs_redraw
+                && !self.layout.iter().any(|widget| match widget {
+                    Widget::Spacer => false,
+                    Widget::User(widget) => widget.is_due(now),
+                })
+        {
+            return;
+        }
+        self.needs_redraw = false;
+        self.draw(qh);
+    }
+
+    /// The next time a widget will be due, if the compositor is ready for a new frame.
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.frame_pending {
+            return None;
+        }
+        let now = Instant::now();
+        if self.needs_redraw {
+            return Some(now);
+        }
+        self.layout
+            .iter()
+            .filter_map(|widget| match widget {
+                Widget::Spacer => None,
+                Widget::User(widget) => Some(widget),
+            })
+            .filter_map(|widget| widget.deadline(now))
+            .min()
+    }
+
     fn draw(&mut self, qh: &QueueHandle<Self>) {
         let width = self.final_resources().width;
         let height = self.config.height;
 
-        let mut buffer = Buffer::new(
-            &mut self.font_system,
-            Metrics {
-                font_size: 14.0,
-                line_height: height as f32,
-            },
-        );
-        let mut buffer = buffer.borrow_with(&mut self.font_system);
-        buffer.set_size(Some(width as f32), Some(height as f32));
-        let attrs = Attrs::new();
-        buffer.shape_until_scroll(true);
-
-        let pool_size = width as usize * height as usize * size_of::<u32>();
-        let mut canvas: Box<[u8]> = iter::repeat_n(0, pool_size).collect();
+        let Some(mut canvas) = Pixmap::new(width, height) else {
+            return;
+        };
         let mut width_usage = 0;
         let mut spacer_count = 0;
         for widget in &self.layout {
@@ -107,67 +139,25 @@ impl Psybeam {
             }
         }
 
-        self.layout.iter().fold(0, |cursor, widget| {
-            match widget {
-                Widget::Spacer => cursor + (width - width_usage) / spacer_count,
-                Widget::User(widget) => {
-                    let mut draw = |instruction: &espy::Value| {
-                        if let Some(bindings::Label {
-                            text,
-                            red,
-                            green,
-                            blue,
-                            alpha,
-                        }) = instruction.downcast_extern()
-                        {
-                            buffer.set_text(text, &attrs, cosmic_text::Shaping::Advanced);
-                            buffer.draw(
-                                &mut self.swash_cache,
-                                Color::rgba(*red, *green, *blue, *alpha),
-                                |x, y, w, h, color| {
-                                    if color.a() == 0 {
-                                        return;
-                                    }
-                                    let x = x + cursor as i32;
-                                    for y in y..(y + h as i32) {
-                                        for x in x..(x + w as i32) {
-                                            let pos =
-                                                (x + y * width as i32) as usize * size_of::<u32>();
-                                            if let Some(dest) =
-                                                canvas.get_mut(pos..(pos + size_of::<u32>()))
-                                            {
-                                                let a = color.a();
-
-                                                let encode =
-                                                    |x: u8| (x as u16 * a as u16 / 255) as u8;
-                                                dest[0] = encode(color.b());
-                                                dest[1] = encode(color.g());
-                                                dest[2] = encode(color.r());
-                                                dest[3] = color.a();
-                                            }
-                                        }
-                                    }
-                                },
-                            );
-                        } else {
-                            eprintln!("unrecognized drawing instruction: {instruction:?}");
-                        }
-                    };
-                    match widget.draw.clone().eval() {
-                        // Unit represents no drawing instructions.
-                        Ok(espy::Value::Unit) => (),
-                        Ok(espy::Value::Tuple(instructions)) => {
-                            instructions.values().for_each(draw)
-                        }
-                        Ok(instruction) => draw(&instruction),
-                        Err(e) => {
-                            eprintln!("widget renderer failed: {e:?}");
-                        }
-                    }
-                    cursor + widget.width
+        self.layout.iter().fold(0, |cursor, widget| match widget {
+            Widget::Spacer => cursor + (width - width_usage) / spacer_count,
+            Widget::User(widget) => {
+                self.renderer.refresh(widget, height, self.frame_time);
+                if let Some(pixmap) = &*widget.state.pixmap.borrow() {
+                    canvas.draw_pixmap(
+                        cursor as i32,
+                        0,
+                        pixmap.as_ref(),
+                        &PixmapPaint::default(),
+                        Transform::identity(),
+                        None,
+                    );
                 }
+                cursor + widget.width
             }
         });
+        let canvas = render::to_argb8888(&canvas);
+        let pool_size = canvas.len();
 
         let resources = self.final_resources();
         let base_surface = &mut resources.base_surface;
@@ -188,6 +178,8 @@ impl Psybeam {
         );
         base_surface.damage(0, 0, width as i32, height as i32);
         base_surface.attach(Some(&buffer), 0, 0);
+        base_surface.commit();
+        self.frame_pending = true;
     }
 }
 
@@ -301,7 +293,6 @@ impl Psybeam {
                 .exclusive_height
                 .unwrap_or(self.config.height as i32),
         );
-        base_surface.frame(qh, ());
         base_surface.commit();
 
         self.resources = PsybeamResources::Final(PsybeamFinal {
@@ -311,6 +302,7 @@ impl Psybeam {
             layer_shell: layer_shell.clone(),
             width,
             layer_surface,
+            configured: false,
         })
     }
 }
@@ -331,7 +323,8 @@ impl Dispatch<ZwlrLayerSurfaceV1, ()> for Psybeam {
                 height: _,
             } => {
                 surface.ack_configure(serial);
-                state.final_resources().base_surface.commit();
+                state.final_resources().configured = true;
+                state.needs_redraw = true;
             }
             zwlr_layer_surface_v1::Event::Closed => {
                 state.running = false;
@@ -355,6 +348,7 @@ impl Dispatch<zxdg_output_v1::ZxdgOutputV1, ()> for Psybeam {
                 resources.width = Some(width as u32);
             } else {
                 state.final_resources().width = width as u32;
+                state.needs_redraw = true;
             }
         }
     }
@@ -367,12 +361,11 @@ impl Dispatch<wl_callback::WlCallback, ()> for Psybeam {
         event: wl_callback::Event,
         _: &(),
         _: &Connection,
-        qh: &QueueHandle<Self>,
+        _: &QueueHandle<Self>,
     ) {
-        if let wl_callback::Event::Done { .. } = event {
-            state.draw(qh);
-            state.final_resources().base_surface.frame(qh, ());
-            state.final_resources().base_surface.commit();
+        if let wl_callback::Event::Done { callback_data } = event {
+            state.frame_pending = false;
+            state.frame_time = callback_data;
         }
     }
 }